@@ -6,3 +6,8 @@ CREATE TABLE IF NOT EXISTS guilds (
     alert_channel text null default null,
     alert_role text null default null
 );
+CREATE TABLE IF NOT EXISTS codes (
+    id integer primary key autoincrement,
+    code varchar(50) not null unique,
+    valid integer not null
+);
@@ -0,0 +1,5 @@
+ALTER TABLE codes ADD COLUMN rewards text not null default '[]';
+ALTER TABLE codes ADD COLUMN expires_at text null default null;
+-- Columns added later cannot default to CURRENT_TIMESTAMP
+ALTER TABLE codes ADD COLUMN first_seen text not null default '';
+UPDATE codes SET first_seen = CURRENT_TIMESTAMP;
@@ -0,0 +1,29 @@
+CREATE TABLE games (
+    id integer primary key autoincrement,
+    name varchar(50) not null unique,
+    slug varchar(20) not null unique
+);
+-- The same code can be listed for several games, each game gets its own row
+CREATE TABLE codes_new (
+    id integer primary key autoincrement,
+    code varchar(50) not null,
+    valid integer not null,
+    rewards text not null default '[]',
+    expires_at text null default null,
+    first_seen text not null default '',
+    game integer not null default 1,
+    unique (game, code)
+);
+INSERT INTO codes_new (id, code, valid, rewards, expires_at, first_seen)
+SELECT id, code, valid, rewards, expires_at, first_seen FROM codes;
+DROP TABLE codes;
+ALTER TABLE codes_new RENAME TO codes;
+CREATE TABLE subscriptions (
+    id integer primary key autoincrement,
+    guild_id text not null,
+    game integer not null references games(id),
+    alert_channel text null default null,
+    alert_role text null default null,
+    enabled integer not null default 1,
+    unique (guild_id, game)
+);
@@ -0,0 +1,13 @@
+CREATE TABLE deliveries (
+    id integer primary key autoincrement,
+    guild_id text not null,
+    code integer not null references codes(id),
+    message_id text null default null,
+    sent_at text null default null,
+    status text not null,
+    unique (guild_id, code)
+);
+-- Everything up to the old watermark has been delivered
+INSERT INTO deliveries (guild_id, code, sent_at, status)
+SELECT g.guild_id, c.id, CURRENT_TIMESTAMP, 'sent' FROM guilds g JOIN codes c ON c.id <= g.last_code;
+ALTER TABLE guilds DROP COLUMN last_code;
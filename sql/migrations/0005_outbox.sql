@@ -0,0 +1,13 @@
+CREATE TABLE outbox (
+    id integer primary key autoincrement,
+    guild_id text not null,
+    game integer not null references games(id),
+    alert_channel text null default null,
+    alert_role text null default null,
+    attempts integer not null default 0,
+    next_attempt_at text not null,
+    last_error text null default null,
+    status text not null default 'pending',
+    created_at text not null default CURRENT_TIMESTAMP
+);
+ALTER TABLE deliveries ADD COLUMN outbox_id integer null default null references outbox(id);
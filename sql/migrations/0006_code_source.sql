@@ -0,0 +1 @@
+ALTER TABLE codes ADD COLUMN source text null default null;
@@ -0,0 +1,2 @@
+ALTER TABLE deliveries ADD COLUMN channel_id text null default null;
+ALTER TABLE deliveries ADD COLUMN shown_expired integer not null default 0;
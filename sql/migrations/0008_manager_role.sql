@@ -0,0 +1 @@
+ALTER TABLE guilds ADD COLUMN manager_role text null default null;
@@ -0,0 +1,46 @@
+CREATE TABLE users (
+    id integer primary key autoincrement,
+    user_id text not null,
+    game integer not null references games(id),
+    dm_channel text not null,
+    created_at text not null default CURRENT_TIMESTAMP,
+    unique (user_id, game)
+);
+-- Alerts go to either a guild or a user, which needs nullable guild ids
+CREATE TABLE outbox_new (
+    id integer primary key autoincrement,
+    guild_id text null default null,
+    user_id text null default null,
+    game integer not null references games(id),
+    alert_channel text null default null,
+    alert_role text null default null,
+    attempts integer not null default 0,
+    next_attempt_at text not null,
+    last_error text null default null,
+    status text not null default 'pending',
+    created_at text not null default CURRENT_TIMESTAMP,
+    check ((guild_id IS NULL) != (user_id IS NULL))
+);
+INSERT INTO outbox_new (id, guild_id, game, alert_channel, alert_role, attempts, next_attempt_at, last_error, status, created_at)
+SELECT id, guild_id, game, alert_channel, alert_role, attempts, next_attempt_at, last_error, status, created_at FROM outbox;
+CREATE TABLE deliveries_new (
+    id integer primary key autoincrement,
+    guild_id text null default null,
+    user_id text null default null,
+    code integer not null references codes(id),
+    message_id text null default null,
+    sent_at text null default null,
+    status text not null,
+    outbox_id integer null default null references outbox(id),
+    channel_id text null default null,
+    shown_expired integer not null default 0,
+    check ((guild_id IS NULL) != (user_id IS NULL)),
+    unique (guild_id, code),
+    unique (user_id, code)
+);
+INSERT INTO deliveries_new (id, guild_id, code, message_id, sent_at, status, outbox_id, channel_id, shown_expired)
+SELECT id, guild_id, code, message_id, sent_at, status, outbox_id, channel_id, shown_expired FROM deliveries;
+DROP TABLE deliveries;
+DROP TABLE outbox;
+ALTER TABLE outbox_new RENAME TO outbox;
+ALTER TABLE deliveries_new RENAME TO deliveries;
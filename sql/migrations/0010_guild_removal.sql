@@ -0,0 +1,2 @@
+-- Guilds the bot was removed from keep their settings until they are purged
+ALTER TABLE guilds ADD COLUMN removed_at text null default null;
@@ -0,0 +1,261 @@
+use serenity::all::{
+    ChannelId, CreateActionRow, CreateButton, CreateEmbed, CreateEmbedFooter, CreateMessage,
+    EditMessage, Http, MessageId, RoleId,
+};
+use serenity::http::HttpError;
+
+use crate::db::TursoCode;
+use crate::games::Game;
+
+/// Discord's error code for requests the bot lacks permissions for.
+pub const MISSING_PERMISSIONS: isize = 50013;
+
+/// Discord allows 25 embed fields and 5 rows of 5 buttons per message.
+const MAX_CODES: usize = 25;
+const BUTTONS_PER_ROW: usize = 5;
+
+/// The message announcing codes of one game.
+///
+/// Rendered as an embed with a redeem button per code. [`Alert::text`] is the
+/// same alert for channels the bot may not post embeds in.
+pub struct Alert<'a> {
+    pub game: Game,
+    pub role: Option<RoleId>,
+    pub codes: &'a [TursoCode],
+}
+
+impl<'a> Alert<'a> {
+    pub fn new(game: Game, role: Option<RoleId>, codes: &'a [TursoCode]) -> Self {
+        Self { game, role, codes }
+    }
+
+    fn header(&self) -> String {
+        if let Some(role) = self.role {
+            format!("New {} codes available <@&{role}>", self.game.name())
+        } else {
+            format!("New {} codes available", self.game.name())
+        }
+    }
+
+    /// Codes that do not fit into the embed.
+    fn overflow(&self) -> usize {
+        self.codes.len().saturating_sub(MAX_CODES)
+    }
+
+    pub fn embed(&self) -> CreateEmbed {
+        let mut embed = CreateEmbed::new()
+            .title(format!("New {} codes", self.game.name()))
+            .thumbnail(self.game.icon_url())
+            .colour(self.game.colour())
+            .fields(
+                self.codes
+                    .iter()
+                    .take(MAX_CODES)
+                    .map(|code| (name(code), details(code), false)),
+            );
+        if self.overflow() > 0 {
+            embed = embed.description(format!("And {} more", self.overflow()));
+        }
+        let mut sources: Vec<&str> = self
+            .codes
+            .iter()
+            .filter_map(|code| code.source.as_deref())
+            .collect();
+        sources.sort();
+        sources.dedup();
+        if !sources.is_empty() {
+            embed = embed.footer(CreateEmbedFooter::new(format!(
+                "Source: {}",
+                sources.join(", ")
+            )));
+        }
+        embed
+    }
+
+    /// A link button to the redemption page of every code in the embed.
+    pub fn buttons(&self) -> Vec<CreateActionRow> {
+        self.codes
+            .iter()
+            .take(MAX_CODES)
+            .collect::<Vec<_>>()
+            .chunks(BUTTONS_PER_ROW)
+            .map(|row| {
+                CreateActionRow::Buttons(
+                    row.iter()
+                        .map(|code| {
+                            CreateButton::new_link(code.game.redeem_url(&code.code))
+                                .label(code.code.clone())
+                                .disabled(!code.is_valid())
+                        })
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
+    pub fn text(&self) -> String {
+        self.codes
+            .iter()
+            .map(line)
+            .fold(self.header(), |acc, elem| acc + "\n" + elem.as_str())
+    }
+
+    /// The embed alert. Mentions only ping from the content, so the header
+    /// stays outside of the embed.
+    pub fn message(&self) -> CreateMessage {
+        CreateMessage::new()
+            .content(self.header())
+            .embed(self.embed())
+            .components(self.buttons())
+    }
+
+    pub fn text_message(&self) -> CreateMessage {
+        CreateMessage::new().content(self.text())
+    }
+
+    /// Posts the alert to `channel`.
+    ///
+    /// Falls back to the text alert where the bot lacks the Embed Links
+    /// permission. Discord either rejects such messages or silently drops the
+    /// embed, the latter is repaired by editing the message.
+    pub async fn post(&self, http: &Http, channel: ChannelId) -> serenity::Result<MessageId> {
+        let message = match channel.send_message(http, self.message()).await {
+            Ok(message) => message,
+            Err(err) if is_missing_permissions(&err) => {
+                warn!(channel=?channel, "Not allowed to send embed. Sending text alert");
+                channel.send_message(http, self.text_message()).await?
+            }
+            Err(err) => return Err(err),
+        };
+        if message.embeds.is_empty() {
+            warn!(channel=?channel, "Embed was dropped. Replacing it with text alert");
+            channel
+                .edit_message(http, message.id, self.text_edit())
+                .await?;
+        }
+        Ok(message.id)
+    }
+
+    /// Renders the alert again in a message sent with [`Alert::message`].
+    pub fn embed_edit(&self) -> EditMessage {
+        EditMessage::new()
+            .embed(self.embed())
+            .components(self.buttons())
+    }
+
+    /// Renders the alert as text in a message Discord dropped the embed of.
+    /// The buttons are kept since they do not need the Embed Links permission.
+    pub fn text_edit(&self) -> EditMessage {
+        EditMessage::new()
+            .content(self.text())
+            .components(self.buttons())
+    }
+}
+
+pub fn is_missing_permissions(err: &serenity::Error) -> bool {
+    matches!(
+        err,
+        serenity::Error::Http(HttpError::UnsuccessfulRequest(response))
+            if response.error.code == MISSING_PERMISSIONS
+    )
+}
+
+/// The code as a quoted markdown line, linking to its redemption page.
+pub fn line(code: &TursoCode) -> String {
+    if !code.is_valid() {
+        return format!("> ~~{}~~ (expired)", code.code);
+    }
+    let mut line = format!("> [{}]({})", code.code, code.game.redeem_url(&code.code));
+    if !code.rewards.is_empty() {
+        line += &format!(" - {}", code.rewards.join(", "));
+    }
+    if let Some(expires_at) = code.expires_at {
+        line += &format!(" (expires {expires_at})");
+    }
+    line
+}
+
+fn name(code: &TursoCode) -> String {
+    if code.is_valid() {
+        code.code.clone()
+    } else {
+        format!("~~{}~~", code.code)
+    }
+}
+
+fn details(code: &TursoCode) -> String {
+    if !code.is_valid() {
+        return "Expired".to_string();
+    }
+    let mut lines = vec![];
+    if !code.rewards.is_empty() {
+        lines.push(code.rewards.join(", "));
+    }
+    if let Some(expires_at) = code.expires_at {
+        lines.push(format!("Expires {expires_at}"));
+    }
+    if lines.is_empty() {
+        lines.push("No details known".to_string());
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, Utc};
+    use serenity::all::RoleId;
+
+    use super::Alert;
+    use crate::db::TursoCode;
+    use crate::games::Game;
+
+    fn code(id: i64) -> TursoCode {
+        TursoCode {
+            id,
+            code: format!("CODE{id}"),
+            valid: 1,
+            rewards: vec!["60 Stellar Jade".to_string(), "5 Credits".to_string()],
+            expires_at: NaiveDate::from_ymd_opt(2024, 3, 1),
+            first_seen: Utc::now(),
+            game: Game::StarRail,
+            source: Some("prydwen".to_string()),
+        }
+    }
+
+    #[test]
+    fn text_lists_every_code() {
+        let codes = [code(1), code(2)];
+        let text = Alert::new(Game::StarRail, Some(RoleId::new(42)), &codes).text();
+        assert_eq!(
+            text,
+            "New Honkai: Star Rail codes available <@&42>
+> [CODE1](https://hsr.hoyoverse.com/gift?code=CODE1) - 60 Stellar Jade, 5 Credits (expires 2024-03-01)
+> [CODE2](https://hsr.hoyoverse.com/gift?code=CODE2) - 60 Stellar Jade, 5 Credits (expires 2024-03-01)"
+        );
+    }
+
+    #[test]
+    fn expired_codes_are_struck_through() {
+        let mut expired = code(1);
+        expired.valid = 0;
+        let codes = [expired, code(2)];
+        let text = Alert::new(Game::StarRail, None, &codes).text();
+        assert_eq!(
+            text,
+            "New Honkai: Star Rail codes available
+> ~~CODE1~~ (expired)
+> [CODE2](https://hsr.hoyoverse.com/gift?code=CODE2) - 60 Stellar Jade, 5 Credits (expires 2024-03-01)"
+        );
+    }
+
+    #[test]
+    fn buttons_fit_discord_limits() {
+        let codes: Vec<_> = (1..=30).map(code).collect();
+        let buttons = Alert::new(Game::StarRail, None, &codes).buttons();
+        assert_eq!(buttons.len(), 5);
+
+        let codes: Vec<_> = (1..=7).map(code).collect();
+        let buttons = Alert::new(Game::StarRail, None, &codes).buttons();
+        assert_eq!(buttons.len(), 2);
+    }
+}
@@ -0,0 +1,22 @@
+use std::env;
+
+use anyhow::Result;
+use hsr_alert_bot::config::Config;
+use libsql::Database;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let config_path = env::var("CONFIG").unwrap_or_else(|_| Config::DEFAULT_PATH.to_string());
+    let config = Config::load(&config_path)?;
+
+    tracing::info!(database = config.database(), "Opening local db");
+    let client = Database::open(config.database())?.connect()?;
+    hsr_alert_bot::init_db(client).await?;
+
+    let mut client =
+        hsr_alert_bot::build_client(config.discord_token()?, config.admin()?.to_string()).await?;
+    client.start_autosharded().await?;
+    Ok(())
+}
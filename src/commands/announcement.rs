@@ -2,25 +2,24 @@ use serenity::all::{
     CommandInteraction, Context, CreateCommand, CreateCommandOption, ResolvedOption, ResolvedValue,
 };
 
-use crate::DB;
+use crate::db::Store;
+use crate::guilds;
 
 pub const CMD_NAME: &'static str = "announcement";
 
-pub async fn run(interaction: &CommandInteraction, ctx: &Context, admin: &String) -> String {
+pub async fn run(
+    interaction: &CommandInteraction,
+    ctx: &Context,
+    db: &dyn Store,
+    admin: &String,
+) -> String {
     return if let Some(ResolvedOption {
         value: ResolvedValue::String(msg),
         ..
     }) = interaction.data.options().first()
     {
         if admin == &interaction.user.id.to_string() {
-            if let Err(error) = DB
-                .read()
-                .await
-                .as_ref()
-                .unwrap()
-                .send_to_all_guilds(msg.to_string(), &ctx)
-                .await
-            {
+            if let Err(error) = guilds::send_to_all_guilds(db, msg.to_string(), &ctx).await {
                 error!("{error}");
                 "Failed to send announcement".to_string()
             } else {
@@ -0,0 +1,151 @@
+use serenity::all::{
+    CommandInteraction, CommandOptionType, CreateCommand, CreateCommandOption, CreateEmbed,
+    CreateInteractionResponseMessage, ResolvedOption, ResolvedValue,
+};
+
+use crate::alert;
+use crate::commands::{game_option, resolve_game};
+use crate::db::{Store, TursoCode};
+use crate::games::Game;
+
+pub const CMD_NAME: &str = "codes";
+
+/// Discord's limit for embed descriptions.
+const MAX_DESCRIPTION: usize = 4096;
+/// Discord's limit for the text of all embeds of a message together.
+const MAX_EMBEDS_TOTAL: usize = 6000;
+
+pub async fn run(
+    interaction: &CommandInteraction,
+    db: &dyn Store,
+) -> CreateInteractionResponseMessage {
+    let public = interaction.data.options().iter().any(|option| {
+        matches!(
+            option,
+            ResolvedOption {
+                name: "public",
+                value: ResolvedValue::Boolean(true),
+                ..
+            }
+        )
+    });
+    let response = CreateInteractionResponseMessage::new().ephemeral(!public);
+
+    let codes = match db.valid_codes().await {
+        Ok(codes) => codes,
+        Err(error) => {
+            error!("{error}");
+            return response.content("Failed to get the current codes.");
+        }
+    };
+    let games = match resolve_game(interaction) {
+        Some(game) => vec![game],
+        None => Game::ALL.to_vec(),
+    };
+    let embeds = embeds(&codes, games);
+    if embeds.is_empty() {
+        response.content("There are no valid codes right now.")
+    } else {
+        response.embeds(embeds)
+    }
+}
+
+/// One embed per game with codes. The embeds share the text Discord allows
+/// per message, space a game does not use is left to the following ones.
+fn embeds(codes: &[TursoCode], games: Vec<Game>) -> Vec<CreateEmbed> {
+    let lists: Vec<(Game, Vec<&TursoCode>)> = games
+        .into_iter()
+        .filter_map(|game| {
+            let codes: Vec<&TursoCode> = codes.iter().filter(|code| code.game == game).collect();
+            (!codes.is_empty()).then_some((game, codes))
+        })
+        .collect();
+    let mut left = MAX_EMBEDS_TOTAL;
+    let mut embeds = Vec::with_capacity(lists.len());
+    for (i, (game, codes)) in lists.iter().enumerate() {
+        let (embed, used) = embed(*game, codes, left / (lists.len() - i));
+        left -= used;
+        embeds.push(embed);
+    }
+    embeds
+}
+
+/// The embed listing the codes of the game and the length of its text, which
+/// stays within `budget`.
+fn embed(game: Game, codes: &[&TursoCode], budget: usize) -> (CreateEmbed, usize) {
+    let title = format!("{} codes", game.name());
+    let limit = MAX_DESCRIPTION.min(budget.saturating_sub(title.len()));
+    let mut description = String::new();
+    for (listed, code) in codes.iter().enumerate() {
+        let line = alert::line(code);
+        // Leaves room for the note about the codes left out
+        if description.len() + line.len() + 1 > limit.saturating_sub(32) {
+            description += &format!("And {} more", codes.len() - listed);
+            break;
+        }
+        description += &line;
+        description.push('\n');
+    }
+    let used = title.len() + description.len();
+    let embed = CreateEmbed::new()
+        .title(title)
+        .thumbnail(game.icon_url())
+        .colour(game.colour())
+        .description(description);
+    (embed, used)
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new(CMD_NAME)
+        .description("List the codes that can be redeemed right now")
+        .add_option(game_option("Only list codes of this game"))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "public",
+            "Show the list to everyone in the channel",
+        ))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::{embeds, MAX_EMBEDS_TOTAL};
+    use crate::db::TursoCode;
+    use crate::games::Game;
+
+    fn code(id: i64, game: Game) -> TursoCode {
+        TursoCode {
+            id,
+            code: format!("CODE{id:06}"),
+            valid: 1,
+            rewards: vec!["60 Stellar Jade".to_string(), "10,000 Credits".to_string()],
+            expires_at: None,
+            first_seen: Utc::now(),
+            game,
+            source: None,
+        }
+    }
+
+    #[test]
+    fn embeds_share_the_message_limit() {
+        let codes: Vec<TursoCode> = Game::ALL
+            .into_iter()
+            .flat_map(|game| (0..100).map(move |id| code(id * 3 + game.id(), game)))
+            .collect();
+        let embeds = serde_json::to_value(embeds(&codes, Game::ALL.to_vec())).unwrap();
+        let embeds = embeds.as_array().unwrap();
+        assert_eq!(embeds.len(), 3);
+        let total: usize = embeds
+            .iter()
+            .map(|embed| {
+                embed["title"].as_str().unwrap().chars().count()
+                    + embed["description"].as_str().unwrap().chars().count()
+            })
+            .sum();
+        assert!(total <= MAX_EMBEDS_TOTAL, "{total} characters");
+        for embed in embeds {
+            assert!(embed["description"].as_str().unwrap().ends_with("more"));
+        }
+    }
+}
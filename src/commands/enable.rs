@@ -1,18 +1,28 @@
-use crate::DB;
+use crate::commands::{game_option, resolve_game};
+use crate::db::Store;
 use serenity::{all::CommandInteraction, builder::CreateCommand};
 
 pub const CMD_NAME: &'static str = "enable";
 
-pub async fn run(interaction: &CommandInteraction) -> String {
+pub async fn run(interaction: &CommandInteraction, db: &dyn Store) -> String {
     return if let Some(guild_id) = interaction.guild_id {
-        if let Err(error) = DB
-            .read()
-            .await
-            .as_ref()
-            .unwrap()
-            .set_guild_state(guild_id, true)
-            .await
-        {
+        if let Some(game) = resolve_game(interaction) {
+            if let Err(error) = db.set_subscription_state(guild_id, game, true).await {
+                tracing::error!("{error}");
+                return "Failed to enable alerts.".to_string();
+            }
+            if let Err(error) = db.set_guild_state(guild_id, true).await {
+                tracing::error!("{error}");
+                return "Failed to enable alerts.".to_string();
+            }
+            tracing::info!(
+                "Enabled {} for guild {guild_id} on request of {}",
+                game.name(),
+                interaction.user.name
+            );
+            return format!("{} alerts enabled!", game.name());
+        }
+        if let Err(error) = db.set_guild_state(guild_id, true).await {
             tracing::error!("{error}");
             "Failed to enable alerts.".to_string()
         } else {
@@ -28,5 +38,8 @@ pub async fn run(interaction: &CommandInteraction) -> String {
 }
 
 pub fn register() -> CreateCommand {
-    CreateCommand::new(CMD_NAME).description("Enable alerts for this server")
+    CreateCommand::new(CMD_NAME)
+        .description("Enable alerts for this server")
+        .add_option(game_option("Only enable alerts for this game"))
+        .dm_permission(false)
 }
@@ -0,0 +1,52 @@
+use crate::db::Store;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, CreateCommand, CreateCommandOption, ResolvedOption,
+    ResolvedValue,
+};
+
+pub const CMD_NAME: &str = "manager-role";
+
+pub async fn run(interaction: &CommandInteraction, db: &dyn Store) -> String {
+    let options = interaction.data.options();
+    let role = options.iter().find_map(|option| match option {
+        ResolvedOption {
+            value: ResolvedValue::Role(role),
+            ..
+        } => Some(role),
+        _ => None,
+    });
+    let Some(guild_id) = interaction.guild_id else {
+        return "Command run from something that is not a guild".to_string();
+    };
+    if let Err(error) = db
+        .set_guild_manager_role(guild_id, role.map(|role| role.id))
+        .await
+    {
+        tracing::error!("{error}");
+        return "Could not set the manager role due to an internal error".to_string();
+    }
+    tracing::info!(
+        "Set manager role for guild {guild_id} to {:?} on request of {}",
+        role.map(|role| &role.name),
+        interaction.user.name
+    );
+    if let Some(role) = role {
+        format!(
+            "Members with {} can now configure the bot without the Manage Server permission",
+            role.name
+        )
+    } else {
+        "Removed the manager role. Only members with the Manage Server permission can configure the bot".to_string()
+    }
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new(CMD_NAME)
+        .description("Let members with a role configure the bot. Leave empty to remove it")
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Role,
+            "role",
+            "The role allowed to configure the bot",
+        ))
+        .dm_permission(false)
+}
@@ -1,13 +1,26 @@
-use serenity::all::Command;
-use serenity::all::{CacheHttp, CreateCommand};
+use anyhow::Result;
+use serenity::all::{CacheHttp, CreateCommand, CreateInteractionResponseMessage};
+use serenity::all::{
+    Command, CommandInteraction, CommandOptionType, CreateCommandOption, ResolvedOption,
+    ResolvedValue,
+};
 use serenity::async_trait;
 
+use crate::db::Store;
+use crate::games::Game;
+
 pub mod announcement;
+pub mod codes;
 pub mod disable;
 pub mod enable;
+pub mod manager_role;
+pub mod role_panel;
 pub mod set_alert_channel;
 pub mod set_alert_role;
+pub mod status;
 pub mod subscribe;
+pub mod test_alert;
+pub mod unsubscribe;
 
 #[async_trait]
 pub trait CreateCommandVecExt {
@@ -24,3 +37,217 @@ impl CreateCommandVecExt for Vec<CreateCommand> {
         }
     }
 }
+
+/// Commands changing or revealing the settings of a guild. Only members
+/// allowed by [`can_configure`] may run them.
+pub const CONFIGURATION: [&str; 8] = [
+    enable::CMD_NAME,
+    disable::CMD_NAME,
+    set_alert_channel::CMD_NAME,
+    set_alert_role::CMD_NAME,
+    manager_role::CMD_NAME,
+    role_panel::CMD_NAME,
+    status::CMD_NAME,
+    test_alert::CMD_NAME,
+];
+
+/// Whether the invoker may configure the bot: members with Manage Server and,
+/// except for changing the manager role itself, members with the guild's
+/// manager role.
+pub async fn can_configure(interaction: &CommandInteraction, db: &dyn Store) -> Result<bool> {
+    let (Some(guild_id), Some(member)) = (interaction.guild_id, &interaction.member) else {
+        return Ok(false);
+    };
+    // Resolved by Discord for the invoking member, administrators included
+    if member
+        .permissions
+        .is_some_and(|permissions| permissions.manage_guild())
+    {
+        return Ok(true);
+    }
+    if interaction.data.name == manager_role::CMD_NAME {
+        return Ok(false);
+    }
+    let manager_role = db.guild(guild_id).await?.and_then(|g| g.manager_role);
+    Ok(manager_role.is_some_and(|role| member.roles.contains(&role)))
+}
+
+/// A plain text response visible to everyone.
+pub fn reply(content: String) -> CreateInteractionResponseMessage {
+    CreateInteractionResponseMessage::new().content(content)
+}
+
+/// Optional `game` option offering every supported game as a choice.
+pub fn game_option(description: &str) -> CreateCommandOption {
+    Game::ALL.into_iter().fold(
+        CreateCommandOption::new(CommandOptionType::String, "game", description),
+        |option, game| option.add_string_choice(game.name(), game.slug()),
+    )
+}
+
+/// The game picked in the `game` option, if any. Options of a subcommand are
+/// searched as well.
+pub fn resolve_game(interaction: &CommandInteraction) -> Option<Game> {
+    find_game(&interaction.data.options())
+}
+
+fn find_game(options: &[ResolvedOption]) -> Option<Game> {
+    options.iter().find_map(|option| match option {
+        ResolvedOption {
+            name: "game",
+            value: ResolvedValue::String(slug),
+            ..
+        } => Game::from_slug(slug),
+        ResolvedOption {
+            value: ResolvedValue::SubCommand(options),
+            ..
+        } => find_game(options),
+        _ => None,
+    })
+}
+
+/// The name of the subcommand that was run, if any.
+pub fn subcommand(interaction: &CommandInteraction) -> Option<&str> {
+    interaction
+        .data
+        .options()
+        .into_iter()
+        .find_map(|option| match option.value {
+            ResolvedValue::SubCommand(_) => Some(option.name),
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use serenity::all::{CommandInteraction, GuildId, RoleId};
+
+    use super::{can_configure, disable, enable, manager_role, status};
+    use crate::db::{MemoryStore, Store};
+    use crate::games::Game;
+
+    /// An interaction running `name` with `options`, from a guild if
+    /// `member` is given.
+    fn interaction(name: &str, options: Value, member: Option<Value>) -> CommandInteraction {
+        let user = json!({"id": "7", "username": "tester", "discriminator": "0", "avatar": null});
+        let mut interaction = json!({
+            "id": "1",
+            "application_id": "2",
+            "data": {"id": "3", "name": name, "type": 1, "options": options},
+            "channel_id": "4",
+            "user": user.clone(),
+            "token": "token",
+            "version": 1,
+            "app_permissions": null,
+            "locale": "en-US",
+            "guild_locale": null,
+            "entitlements": [],
+        });
+        if let Some(mut member) = member {
+            member["user"] = user;
+            interaction["guild_id"] = json!("1");
+            interaction["member"] = member;
+        }
+        serde_json::from_value(interaction).unwrap()
+    }
+
+    fn member(roles: &[&str], permissions: &str) -> Value {
+        json!({
+            "roles": roles,
+            "joined_at": null,
+            "deaf": false,
+            "mute": false,
+            "flags": 0,
+            "permissions": permissions,
+        })
+    }
+
+    fn game(slug: &str) -> Value {
+        json!([{"name": "game", "type": 3, "value": slug}])
+    }
+
+    #[tokio::test]
+    async fn games_are_enabled_and_disabled() {
+        let store = MemoryStore::new();
+        store.try_add_guild(GuildId::new(1)).await.unwrap();
+        let admin = || Some(member(&[], "32"));
+
+        let disabled = interaction(disable::CMD_NAME, game("hsr"), admin());
+        assert_eq!(
+            disable::run(&disabled, &store).await,
+            "Honkai: Star Rail alerts disabled!"
+        );
+        let subscriptions = store.subscriptions(GuildId::new(1)).await.unwrap();
+        let star_rail = subscriptions
+            .iter()
+            .find(|subscription| subscription.game == Game::StarRail)
+            .unwrap();
+        assert_eq!(star_rail.enabled, 0);
+
+        let everything = interaction(disable::CMD_NAME, json!([]), admin());
+        assert_eq!(disable::run(&everything, &store).await, "Alerts disabled!");
+        assert_eq!(
+            store.guild(GuildId::new(1)).await.unwrap().unwrap().enabled,
+            0
+        );
+
+        let enabled = interaction(enable::CMD_NAME, game("hsr"), admin());
+        assert_eq!(
+            enable::run(&enabled, &store).await,
+            "Honkai: Star Rail alerts enabled!"
+        );
+        assert_eq!(
+            store.guild(GuildId::new(1)).await.unwrap().unwrap().enabled,
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn guild_commands_outside_of_a_guild() {
+        let store = MemoryStore::new();
+        let dm = interaction(enable::CMD_NAME, json!([]), None);
+        assert_eq!(
+            enable::run(&dm, &store).await,
+            "Command run from something that is not a guild"
+        );
+        assert!(!can_configure(&dm, &store).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn managers_configure_but_cannot_change_the_manager_role() {
+        let store = MemoryStore::new();
+        store.try_add_guild(GuildId::new(1)).await.unwrap();
+        store
+            .set_guild_manager_role(GuildId::new(1), Some(RoleId::new(5)))
+            .await
+            .unwrap();
+
+        let manager = || Some(member(&["5"], "0"));
+        let everyone = || Some(member(&["6"], "0"));
+        let admin = || Some(member(&[], "32"));
+        assert!(
+            can_configure(&interaction(status::CMD_NAME, json!([]), manager()), &store)
+                .await
+                .unwrap()
+        );
+        assert!(!can_configure(
+            &interaction(status::CMD_NAME, json!([]), everyone()),
+            &store
+        )
+        .await
+        .unwrap());
+        assert!(!can_configure(
+            &interaction(manager_role::CMD_NAME, json!([]), manager()),
+            &store
+        )
+        .await
+        .unwrap());
+        assert!(can_configure(
+            &interaction(manager_role::CMD_NAME, json!([]), admin()),
+            &store
+        )
+        .await
+        .unwrap());
+    }
+}
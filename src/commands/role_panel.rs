@@ -0,0 +1,140 @@
+use serenity::all::{
+    ButtonStyle, CommandInteraction, ComponentInteraction, Context, CreateActionRow, CreateButton,
+    CreateCommand, CreateEmbed, CreateInteractionResponseMessage, CreateMessage,
+};
+
+use crate::commands::{game_option, resolve_game, subscribe, unsubscribe};
+use crate::db::Store;
+use crate::games::Game;
+
+pub const CMD_NAME: &str = "role-panel";
+
+/// Custom ids of the panel's buttons, followed by `:` and the slug of the
+/// game. They carry no other state, the role is looked up on every click, so
+/// panels keep working across restarts and after the alert role changed.
+/// Panels posted before games were added use the ids without a game.
+pub const SUBSCRIBE_BUTTON: &str = "role-panel:subscribe";
+pub const UNSUBSCRIBE_BUTTON: &str = "role-panel:unsubscribe";
+
+/// Posts the panel to the channel the command was run in.
+pub async fn run(
+    interaction: &CommandInteraction,
+    ctx: &Context,
+    db: &dyn Store,
+) -> CreateInteractionResponseMessage {
+    let response = CreateInteractionResponseMessage::new().ephemeral(true);
+    let Some(guild_id) = interaction.guild_id else {
+        return response.content("Command run from something that is not a guild");
+    };
+    let game = resolve_game(interaction).unwrap_or(Game::DEFAULT);
+    match db.game_alert_role(guild_id, game).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return response
+                .content("Set an alert role using `/alert-role` before posting the panel.")
+        }
+        Err(error) => {
+            error!("{error}");
+            return response.content("Could not post the panel due to an internal error");
+        }
+    }
+
+    match interaction
+        .channel_id
+        .send_message(&ctx.http, panel(game))
+        .await
+    {
+        Ok(_) => response.content("Posted the panel."),
+        Err(error) => {
+            error!("{error}");
+            response.content(format!("Could not post the panel: {error}"))
+        }
+    }
+}
+
+fn panel(game: Game) -> CreateMessage {
+    CreateMessage::new()
+        .embed(
+            CreateEmbed::new()
+                .title(format!("Get {} code alerts", game.name()))
+                .description("Get pinged whenever new redemption codes are released."),
+        )
+        .components(vec![CreateActionRow::Buttons(vec![
+            CreateButton::new(format!("{SUBSCRIBE_BUTTON}:{}", game.slug()))
+                .label("Subscribe")
+                .style(ButtonStyle::Success),
+            CreateButton::new(format!("{UNSUBSCRIBE_BUTTON}:{}", game.slug()))
+                .label("Unsubscribe")
+                .style(ButtonStyle::Secondary),
+        ])])
+}
+
+/// The button and game of a panel button's custom id.
+fn parse_button(custom_id: &str) -> Option<(&'static str, Game)> {
+    [SUBSCRIBE_BUTTON, UNSUBSCRIBE_BUTTON]
+        .into_iter()
+        .find_map(|button| {
+            let game = match custom_id.strip_prefix(button)? {
+                "" => Game::DEFAULT,
+                rest => Game::from_slug(rest.strip_prefix(':')?)?,
+            };
+            Some((button, game))
+        })
+}
+
+/// Handles a click on one of the panel's buttons. Returns `None` for other
+/// components.
+pub async fn handle(
+    component: &ComponentInteraction,
+    ctx: &Context,
+    db: &dyn Store,
+) -> Option<CreateInteractionResponseMessage> {
+    let guild_id = component.guild_id;
+    let member = component.member.as_ref();
+    let content = match parse_button(&component.data.custom_id)? {
+        (SUBSCRIBE_BUTTON, game) => {
+            subscribe::add_alert_role(ctx, db, guild_id, member, game).await
+        }
+        (_, game) => unsubscribe::remove_alert_role(ctx, db, guild_id, member, game).await,
+    };
+    Some(
+        CreateInteractionResponseMessage::new()
+            .ephemeral(true)
+            .content(content),
+    )
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new(CMD_NAME)
+        .description("Post a message with buttons to get or remove the alert role")
+        .add_option(game_option(
+            "Post the buttons for the alert role of this game",
+        ))
+        .dm_permission(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_button, SUBSCRIBE_BUTTON, UNSUBSCRIBE_BUTTON};
+    use crate::games::Game;
+
+    #[test]
+    fn buttons_carry_their_game() {
+        assert_eq!(
+            parse_button("role-panel:subscribe:genshin"),
+            Some((SUBSCRIBE_BUTTON, Game::Genshin))
+        );
+        assert_eq!(
+            parse_button("role-panel:unsubscribe:zzz"),
+            Some((UNSUBSCRIBE_BUTTON, Game::ZenlessZoneZero))
+        );
+        // Posted before the panel had a game
+        assert_eq!(
+            parse_button("role-panel:subscribe"),
+            Some((SUBSCRIBE_BUTTON, Game::DEFAULT))
+        );
+        assert_eq!(parse_button("role-panel:subscribe:unknown"), None);
+        assert_eq!(parse_button("role-panel:subscribers"), None);
+        assert_eq!(parse_button("other"), None);
+    }
+}
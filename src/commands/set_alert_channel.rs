@@ -1,4 +1,5 @@
-use crate::DB;
+use crate::commands::{game_option, resolve_game};
+use crate::db::Store;
 use serenity::all::{
     CommandInteraction, CommandOptionType, CreateCommand, CreateCommandOption, ResolvedOption,
     ResolvedValue,
@@ -6,57 +7,76 @@ use serenity::all::{
 
 pub const CMD_NAME: &'static str = "alert-channel";
 
-pub async fn run(interaction: &CommandInteraction) -> String {
-    let db_opt = DB.read().await;
-    let db = db_opt.as_ref().unwrap();
-    return if let Some(ResolvedOption {
-        value: ResolvedValue::Channel(channel),
-        ..
-    }) = interaction.data.options().first()
-    {
-        if let Some(guild_id) = interaction.guild_id {
-            // TODO check if channel text channel
-            if let Err(error) = db.set_guild_alert_channel(guild_id, Some(channel.id)).await {
-                tracing::error!("{error}");
-                "Could not set alert channel due to an internal error".to_string()
+pub async fn run(interaction: &CommandInteraction, db: &dyn Store) -> String {
+    let game = resolve_game(interaction);
+    let options = interaction.data.options();
+    let channel = options.iter().find_map(|option| match option {
+        ResolvedOption {
+            value: ResolvedValue::Channel(channel),
+            ..
+        } => Some(channel),
+        _ => None,
+    });
+    let Some(guild_id) = interaction.guild_id else {
+        return "Command run from something that is not a guild".to_string();
+    };
+    if let Some(channel) = channel {
+        // TODO check if channel text channel
+        let res = if let Some(game) = game {
+            db.set_subscription_channel(guild_id, game, Some(channel.id))
+                .await
+        } else {
+            db.set_guild_alert_channel(guild_id, Some(channel.id)).await
+        };
+        if let Err(error) = res {
+            tracing::error!("{error}");
+            "Could not set alert channel due to an internal error".to_string()
+        } else {
+            tracing::info!(
+                "Set alert channel for guild {guild_id} to {} on request of {}",
+                channel.name.as_ref().unwrap(),
+                interaction.user.name
+            );
+            if let Some(game) = game {
+                format!(
+                    "Set {} alert channel to: {}",
+                    game.name(),
+                    channel.name.as_ref().expect("No name?")
+                )
             } else {
-                tracing::info!(
-                    "Set alert channel for guild {guild_id} to {} on request of {}",
-                    channel.name.as_ref().unwrap(),
-                    interaction.user.name
-                );
                 format!(
                     "Set alert channel to: {}",
                     channel.name.as_ref().expect("No name?")
                 )
             }
-        } else {
-            "Command run from something that is not a guild".to_string()
         }
     } else {
-        if let Some(guild_id) = interaction.guild_id {
-            if let Err(err) = db.set_guild_alert_channel(guild_id, None).await {
-                tracing::error!("Error: {}", err);
-                "Could not remove the alert channel because of an internal error".to_string()
-            } else {
-                tracing::info!(
-                    "Removed alerts channel at request of {}",
-                    interaction.user.name
-                );
-                "Successfully removed the alert channel".to_string()
-            }
+        let res = if let Some(game) = game {
+            db.set_subscription_channel(guild_id, game, None).await
         } else {
-            "Command run from something that is not a guild".to_string()
+            db.set_guild_alert_channel(guild_id, None).await
+        };
+        if let Err(err) = res {
+            tracing::error!("Error: {}", err);
+            "Could not remove the alert channel because of an internal error".to_string()
+        } else {
+            tracing::info!(
+                "Removed alerts channel at request of {}",
+                interaction.user.name
+            );
+            "Successfully removed the alert channel".to_string()
         }
-    };
+    }
 }
 
 pub fn register() -> CreateCommand {
     CreateCommand::new(CMD_NAME)
-        .description("Enable alerts for this server")
+        .description("Set the alert channel for this server")
         .add_option(CreateCommandOption::new(
             CommandOptionType::Channel,
             "channel",
             "The channel to use as an alert channel",
         ))
+        .add_option(game_option("Only use the channel for alerts of this game"))
+        .dm_permission(false)
 }
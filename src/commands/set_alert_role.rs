@@ -1,4 +1,5 @@
-use crate::DB;
+use crate::commands::{game_option, resolve_game};
+use crate::db::Store;
 use serenity::all::{
     CommandInteraction, CommandOptionType, CreateCommand, CreateCommandOption, ResolvedOption,
     ResolvedValue,
@@ -6,45 +7,58 @@ use serenity::all::{
 
 pub const CMD_NAME: &'static str = "alert-role";
 
-pub async fn run(interaction: &CommandInteraction) -> String {
-    let db_opt = DB.read().await;
-    let db = db_opt.as_ref().unwrap();
-    return if let Some(ResolvedOption {
-        value: ResolvedValue::Role(role),
-        ..
-    }) = interaction.data.options().first()
-    {
-        if let Some(guild_id) = interaction.guild_id {
-            if let Err(error) = db.set_guild_alert_role(guild_id, Some(role.id)).await {
-                tracing::error!("{error}");
-                "Could not set alert role due to an internal error".to_string()
+pub async fn run(interaction: &CommandInteraction, db: &dyn Store) -> String {
+    let game = resolve_game(interaction);
+    let options = interaction.data.options();
+    let role = options.iter().find_map(|option| match option {
+        ResolvedOption {
+            value: ResolvedValue::Role(role),
+            ..
+        } => Some(role),
+        _ => None,
+    });
+    let Some(guild_id) = interaction.guild_id else {
+        return "Command run from something that is not a guild".to_string();
+    };
+    if let Some(role) = role {
+        let res = if let Some(game) = game {
+            db.set_subscription_role(guild_id, game, Some(role.id))
+                .await
+        } else {
+            db.set_guild_alert_role(guild_id, Some(role.id)).await
+        };
+        if let Err(error) = res {
+            tracing::error!("{error}");
+            "Could not set alert role due to an internal error".to_string()
+        } else {
+            tracing::info!(
+                "Set alert role for guild {guild_id} to {} on request of {}",
+                role.name,
+                interaction.user.name
+            );
+            if let Some(game) = game {
+                format!("Set {} alert role to: {}", game.name(), role.name)
             } else {
-                tracing::info!(
-                    "Set alert role for guild {guild_id} to {} on request of {}",
-                    role.name,
-                    interaction.user.name
-                );
                 format!("Set alert role to: {}", role.name)
             }
-        } else {
-            "Command run from something that is not a guild".to_string()
         }
     } else {
-        if let Some(guild_id) = interaction.guild_id {
-            if let Err(err) = db.set_guild_alert_role(guild_id, None).await {
-                tracing::error!("Error: {}", err);
-                "Could not remove the alert role because of an internal error".to_string()
-            } else {
-                tracing::info!(
-                    "Removed alerts role at request of {}",
-                    interaction.user.name
-                );
-                "Successfully removed the alert role".to_string()
-            }
+        let res = if let Some(game) = game {
+            db.set_subscription_role(guild_id, game, None).await
         } else {
-            "Command run from something that is not a guild".to_string()
+            db.set_guild_alert_role(guild_id, None).await
+        };
+        if let Err(err) = res {
+            tracing::error!("Error: {}", err);
+            "Could not remove the alert role because of an internal error".to_string()
+        } else {
+            tracing::info!(
+                "Removed alerts role at request of {}",
+                interaction.user.name
+            );
+            "Successfully removed the alert role".to_string()
         }
-    };
+    }
 }
 
 pub fn register() -> CreateCommand {
@@ -55,4 +69,6 @@ pub fn register() -> CreateCommand {
             "role",
             "The role to use as an alert role",
         ))
+        .add_option(game_option("Only use the role for alerts of this game"))
+        .dm_permission(false)
 }
@@ -0,0 +1,115 @@
+use anyhow::Result;
+use serenity::all::{
+    CommandInteraction, Context, CreateAllowedMentions, CreateCommand,
+    CreateInteractionResponseMessage, GuildId,
+};
+
+use crate::db::Store;
+use crate::guilds;
+
+pub const CMD_NAME: &str = "status";
+
+pub async fn run(
+    interaction: &CommandInteraction,
+    ctx: &Context,
+    db: &dyn Store,
+) -> CreateInteractionResponseMessage {
+    // Only shown to the invoker and never pings the alert role
+    let response = CreateInteractionResponseMessage::new()
+        .ephemeral(true)
+        .allowed_mentions(CreateAllowedMentions::new());
+    let Some(guild_id) = interaction.guild_id else {
+        return response.content("Command run from something that is not a guild");
+    };
+    match status(guild_id, ctx, db).await {
+        Ok(status) => response.content(status),
+        Err(error) => {
+            error!("{error}");
+            response.content("Could not get the status of this server due to an internal error")
+        }
+    }
+}
+
+async fn status(guild_id: GuildId, ctx: &Context, db: &dyn Store) -> Result<String> {
+    let Some(guild) = db.guild(guild_id).await? else {
+        return Ok("This server is not known yet. Try again in a few minutes.".to_string());
+    };
+    let mut lines = vec![];
+
+    lines.push(format!(
+        "**Alerts:** {}",
+        if guild.enabled == 1 {
+            "enabled"
+        } else {
+            "disabled"
+        }
+    ));
+    lines.push(match db.guild_alert_channel(guild_id).await? {
+        Some(channel) => format!("**Alert channel:** <#{channel}>"),
+        None => "**Alert channel:** not set".to_string(),
+    });
+    lines.push(match db.guild_alert_role(guild_id).await? {
+        Some(role) => format!("**Alert role:** <@&{role}>"),
+        None => "**Alert role:** none, alerts do not ping".to_string(),
+    });
+
+    lines.push("**Games:**".to_string());
+    for subscription in db.guild_subscriptions(guild_id).await? {
+        let mut line = format!(
+            "- {}: {}",
+            subscription.game.name(),
+            if subscription.enabled == 1 {
+                "enabled"
+            } else {
+                "disabled"
+            }
+        );
+        if let Some(channel) = subscription.alert_channel {
+            line += &format!(", channel <#{channel}>");
+        }
+        if let Some(role) = subscription.alert_role {
+            line += &format!(", role <@&{role}>");
+        }
+        lines.push(line);
+    }
+
+    let stats = db.delivery_stats(guild_id).await?;
+    lines.push(match stats.last_sent {
+        Some((code, Some(sent_at))) => format!(
+            "**Last delivered code:** {} ({}) <t:{}:R>",
+            code.code,
+            code.game.name(),
+            sent_at.timestamp()
+        ),
+        Some((code, None)) => format!(
+            "**Last delivered code:** {} ({})",
+            code.code,
+            code.game.name()
+        ),
+        None => "**Last delivered code:** none yet".to_string(),
+    });
+    lines.push(format!("**Pending deliveries:** {}", stats.queued));
+    if stats.failed > 0 {
+        lines.push(format!("**Failed deliveries:** {}", stats.failed));
+    }
+
+    match guilds::check_guild(db, &guild, ctx).await {
+        Ok(problems) if problems.is_empty() => lines.push("**Problems:** none found".to_string()),
+        Ok(problems) => {
+            lines.push("**Problems:**".to_string());
+            lines.extend(problems.iter().map(|problem| format!("- {problem}")));
+        }
+        Err(error) => {
+            warn!(guild=?guild_id, reason = error.to_string(), "Could not check guild");
+            lines.push("**Problems:** could not check the server settings".to_string());
+        }
+    }
+
+    Ok(lines.join("\n"))
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new(CMD_NAME)
+        .description("Show the alert settings of this server and any problems with them")
+        .dm_permission(false)
+}
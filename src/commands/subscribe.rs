@@ -1,36 +1,111 @@
-use crate::DB;
-use serenity::all::Context;
+use crate::commands::{game_option, resolve_game, subcommand};
+use crate::db::Store;
+use crate::games::Game;
+use crate::guilds;
+use serenity::all::{CommandOptionType, Context, CreateCommandOption, GuildId, Member};
 use serenity::{all::CommandInteraction, builder::CreateCommand};
 
 pub const CMD_NAME: &'static str = "subscribe";
 
-pub async fn run(interaction: &CommandInteraction, ctx: &Context) -> String {
-    return if let Some(guild_id) = interaction.guild_id {
-        return if let Some(member) = &interaction.member {
-            if let Ok(Some(role)) = DB
-                .read()
-                .await
-                .as_ref()
-                .unwrap()
-                .guild_alert_role(guild_id)
-                .await
-            {
-                if let Ok(()) = member.add_role(&ctx, role).await {
-                    "Subscribed you to the alerts!".to_string()
-                } else {
-                    "Could not add the role!".to_string()
-                }
-            } else {
-                "The alert role is not enabled on your server. You might want to add one using `/alert-role`".to_string()
-            }
-        } else {
-            "Apparently you are not member of this server???".to_string()
-        };
-    } else {
-        "Command run from something that is not a guild".to_string()
+pub async fn run(interaction: &CommandInteraction, ctx: &Context, db: &dyn Store) -> String {
+    match subcommand(interaction) {
+        Some("role") => {
+            let game = resolve_game(interaction).unwrap_or(Game::DEFAULT);
+            add_alert_role(
+                ctx,
+                db,
+                interaction.guild_id,
+                interaction.member.as_deref(),
+                game,
+            )
+            .await
+        }
+        Some("dm") => dm(interaction, ctx, db).await,
+        _ => "Unknown subcommand".to_string(),
+    }
+}
+
+/// Gives the member the role pinged for alerts of `game`.
+pub async fn add_alert_role(
+    ctx: &Context,
+    db: &dyn Store,
+    guild_id: Option<GuildId>,
+    member: Option<&Member>,
+    game: Game,
+) -> String {
+    let (Some(guild_id), Some(member)) = (guild_id, member) else {
+        return "Use `/subscribe dm` to get alerts outside of a server".to_string();
+    };
+    let role = match db.game_alert_role(guild_id, game).await {
+        Ok(Some(role)) => role,
+        Ok(None) => return "The alert role is not enabled on your server. You might want to add one using `/alert-role`".to_string(),
+        Err(error) => {
+            error!("{error}");
+            return "Failed to get the alert role.".to_string();
+        }
+    };
+    if member.roles.contains(&role) {
+        return "You are already subscribed to the alerts. Use `/unsubscribe role` to stop them."
+            .to_string();
+    }
+    match guilds::check_role(ctx, guild_id, role).await {
+        Ok(Some(problem)) => return problem.to_string(),
+        Ok(None) => {}
+        // Adding the role reports the actual error
+        Err(error) => warn!(reason = error.to_string(), "Could not check the alert role"),
+    }
+    match member.add_role(&ctx, role).await {
+        Ok(()) => "Subscribed you to the alerts!".to_string(),
+        Err(error) => {
+            error!("{error}");
+            format!("Could not add the role: {error}")
+        }
+    }
+}
+
+/// Sends the codes of the game to the user by direct message.
+async fn dm(interaction: &CommandInteraction, ctx: &Context, db: &dyn Store) -> String {
+    let game = resolve_game(interaction).unwrap_or(Game::DEFAULT);
+    let channel = match interaction.user.create_dm_channel(&ctx).await {
+        Ok(channel) => channel,
+        Err(error) => {
+            error!("{error}");
+            return "Could not open a direct message with you. Check that you allow direct messages from this server.".to_string();
+        }
     };
+    match db
+        .subscribe_user(interaction.user.id, game, channel.id)
+        .await
+    {
+        Ok(true) => format!(
+            "You will get new {} codes by direct message. Use `/unsubscribe dm` to stop.",
+            game.name()
+        ),
+        Ok(false) => format!("You already get {} codes by direct message.", game.name()),
+        Err(error) => {
+            error!("{error}");
+            "Failed to subscribe you.".to_string()
+        }
+    }
 }
 
 pub fn register() -> CreateCommand {
-    CreateCommand::new(CMD_NAME).description("Enable alerts for this server")
+    CreateCommand::new(CMD_NAME)
+        .description("Get alerts for new codes")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "role",
+                "Get the alert role of this server",
+            )
+            .add_sub_option(game_option("Get the alert role of this game")),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "dm",
+                "Get the codes by direct message",
+            )
+            .add_sub_option(game_option("Get the codes of this game")),
+        )
 }
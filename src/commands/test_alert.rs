@@ -0,0 +1,110 @@
+use chrono::{Days, Utc};
+use serenity::all::{CommandInteraction, Context, CreateCommand, CreateInteractionResponseMessage};
+use serenity::http::HttpError;
+
+use crate::alert::{Alert, MISSING_PERMISSIONS};
+use crate::commands::{game_option, resolve_game};
+use crate::db::{Store, TursoCode};
+use crate::games::Game;
+
+pub const CMD_NAME: &str = "test-alert";
+
+/// Discord's error codes for channels the bot cannot see or that are gone.
+const MISSING_ACCESS: isize = 50001;
+const UNKNOWN_CHANNEL: isize = 10003;
+
+pub async fn run(
+    interaction: &CommandInteraction,
+    ctx: &Context,
+    db: &dyn Store,
+) -> CreateInteractionResponseMessage {
+    let response = CreateInteractionResponseMessage::new().ephemeral(true);
+    let Some(guild_id) = interaction.guild_id else {
+        return response.content("Command run from something that is not a guild");
+    };
+    let game = resolve_game(interaction).unwrap_or(Game::DEFAULT);
+
+    let guild = match db.guild(guild_id).await {
+        Ok(Some(guild)) => guild,
+        Ok(None) => {
+            return response.content("This server is not known yet. Try again in a few minutes.")
+        }
+        Err(error) => {
+            error!("{error}");
+            return response.content("Could not send a test alert due to an internal error");
+        }
+    };
+    let subscription = match db.guild_subscriptions(guild_id).await {
+        Ok(subscriptions) => subscriptions.into_iter().find(|sub| sub.game == game),
+        Err(error) => {
+            error!("{error}");
+            return response.content("Could not send a test alert due to an internal error");
+        }
+    };
+    let channel = subscription
+        .as_ref()
+        .map_or(guild.alert_channel, |sub| sub.channel(&guild));
+    let role = subscription
+        .as_ref()
+        .map_or(guild.alert_role, |sub| sub.role(&guild));
+    let Some(channel) = channel else {
+        return response.content(format!(
+            "No alert channel is set for {}. Set one using `/alert-channel`",
+            game.name()
+        ));
+    };
+
+    let codes = [sample(game)];
+    let alert = Alert::new(game, role, &codes);
+    match alert.post(&ctx.http, channel).await {
+        Ok(_) => {
+            info!(
+                "Sent test alert to guild {guild_id} on request of {}",
+                interaction.user.name
+            );
+            let ping = match role {
+                Some(role) => format!("pinging <@&{role}>"),
+                None => "without pinging anyone".to_string(),
+            };
+            response.content(format!("Sent a test alert to <#{channel}> {ping}."))
+        }
+        Err(error) => {
+            warn!(guild=?guild_id, reason = error.to_string(), "Could not send test alert");
+            response.content(explain(&error, channel.get()))
+        }
+    }
+}
+
+/// A code looking like a real one that cannot be redeemed.
+fn sample(game: Game) -> TursoCode {
+    TursoCode {
+        id: 0,
+        code: "TESTALERT".to_string(),
+        valid: 1,
+        rewards: vec!["Nothing, this is a test".to_string()],
+        expires_at: Utc::now().date_naive().checked_add_days(Days::new(7)),
+        first_seen: Utc::now(),
+        game,
+        source: Some("test alert".to_string()),
+    }
+}
+
+fn explain(error: &serenity::Error, channel: u64) -> String {
+    let code = match error {
+        serenity::Error::Http(HttpError::UnsuccessfulRequest(response)) => response.error.code,
+        _ => return format!("Could not send the test alert: {error}"),
+    };
+    match code {
+        MISSING_PERMISSIONS => format!("The bot is not allowed to post in <#{channel}>. It needs the View Channel and Send Messages permissions there."),
+        MISSING_ACCESS => format!("The bot cannot see <#{channel}>. Give it the View Channel permission there."),
+        UNKNOWN_CHANNEL => "The alert channel does not exist anymore. Set a new one using `/alert-channel`".to_string(),
+        _ => format!("Could not send the test alert: {error}"),
+    }
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new(CMD_NAME)
+        .description("Send a sample alert to the alert channel")
+        .add_option(game_option("Send the alert of this game"))
+        .dm_permission(false)
+}
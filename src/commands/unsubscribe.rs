@@ -0,0 +1,106 @@
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption, GuildId,
+    Member,
+};
+
+use crate::commands::{game_option, resolve_game, subcommand};
+use crate::db::Store;
+use crate::games::Game;
+use crate::guilds;
+
+pub const CMD_NAME: &str = "unsubscribe";
+
+pub async fn run(interaction: &CommandInteraction, ctx: &Context, db: &dyn Store) -> String {
+    match subcommand(interaction) {
+        Some("role") => {
+            let game = resolve_game(interaction).unwrap_or(Game::DEFAULT);
+            remove_alert_role(
+                ctx,
+                db,
+                interaction.guild_id,
+                interaction.member.as_deref(),
+                game,
+            )
+            .await
+        }
+        Some("dm") => dm(interaction, db).await,
+        _ => "Unknown subcommand".to_string(),
+    }
+}
+
+/// Takes the role pinged for alerts of `game` away from the member.
+pub async fn remove_alert_role(
+    ctx: &Context,
+    db: &dyn Store,
+    guild_id: Option<GuildId>,
+    member: Option<&Member>,
+    game: Game,
+) -> String {
+    let (Some(guild_id), Some(member)) = (guild_id, member) else {
+        return "Use `/unsubscribe dm` to stop alerts outside of a server".to_string();
+    };
+    let role = match db.game_alert_role(guild_id, game).await {
+        Ok(Some(role)) => role,
+        Ok(None) => return "The alert role is not enabled on your server.".to_string(),
+        Err(error) => {
+            error!("{error}");
+            return "Failed to get the alert role.".to_string();
+        }
+    };
+    if !member.roles.contains(&role) {
+        return "You are not subscribed to the alerts.".to_string();
+    }
+    match guilds::check_role(ctx, guild_id, role).await {
+        Ok(Some(problem)) => return problem.to_string(),
+        Ok(None) => {}
+        // Removing the role reports the actual error
+        Err(error) => warn!(reason = error.to_string(), "Could not check the alert role"),
+    }
+    match member.remove_role(&ctx, role).await {
+        Ok(()) => "Unsubscribed you from the alerts.".to_string(),
+        Err(error) => {
+            error!("{error}");
+            format!("Could not remove the role: {error}")
+        }
+    }
+}
+
+/// Stops the direct messages of one game, or of all games if none is given.
+async fn dm(interaction: &CommandInteraction, db: &dyn Store) -> String {
+    let game = resolve_game(interaction);
+    match db.unsubscribe_user(interaction.user.id, game).await {
+        Ok(true) => match game {
+            Some(game) => format!(
+                "You will not get {} codes by direct message anymore.",
+                game.name()
+            ),
+            None => "You will not get any codes by direct message anymore.".to_string(),
+        },
+        Ok(false) => "You were not subscribed to these direct messages.".to_string(),
+        Err(error) => {
+            error!("{error}");
+            "Failed to unsubscribe you.".to_string()
+        }
+    }
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new(CMD_NAME)
+        .description("Stop alerts for new codes")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "role",
+                "Remove the alert role of this server",
+            )
+            .add_sub_option(game_option("Remove the alert role of this game")),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "dm",
+                "Stop the codes sent by direct message",
+            )
+            .add_sub_option(game_option("Only stop the codes of this game")),
+        )
+}
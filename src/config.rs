@@ -0,0 +1,93 @@
+use std::env;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+/// Settings of the standalone runtime.
+///
+/// Read from a TOML file, with every key overridable by an environment
+/// variable of the same name in upper case (e.g. `DISCORD_TOKEN`).
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Config {
+    pub discord_token: Option<String>,
+    pub admin: Option<String>,
+    /// Path of the local database file
+    pub database: Option<String>,
+}
+
+impl Config {
+    pub const DEFAULT_PATH: &'static str = "config.toml";
+    pub const DEFAULT_DATABASE: &'static str = "hsr-alert-bot.db";
+
+    pub fn from_toml(content: &str) -> Result<Self> {
+        Ok(toml::from_str(content)?)
+    }
+
+    /// Reads the config file at `path` if it exists and applies the environment
+    /// on top.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let mut config = if path.exists() {
+            Self::from_toml(&std::fs::read_to_string(path)?)?
+        } else {
+            Self::default()
+        };
+        config.apply_overrides(|key| env::var(key).ok());
+        Ok(config)
+    }
+
+    fn apply_overrides(&mut self, var: impl Fn(&str) -> Option<String>) {
+        if let Some(token) = var("DISCORD_TOKEN") {
+            self.discord_token = Some(token);
+        }
+        if let Some(admin) = var("ADMIN") {
+            self.admin = Some(admin);
+        }
+        if let Some(database) = var("DATABASE") {
+            self.database = Some(database);
+        }
+    }
+
+    pub fn discord_token(&self) -> Result<&str> {
+        self.discord_token
+            .as_deref()
+            .ok_or_else(|| anyhow!("No discord token provided. Set discord_token or DISCORD_TOKEN"))
+    }
+
+    pub fn admin(&self) -> Result<&str> {
+        self.admin
+            .as_deref()
+            .ok_or_else(|| anyhow!("No admin provided. Set admin or ADMIN"))
+    }
+
+    pub fn database(&self) -> &str {
+        self.database.as_deref().unwrap_or(Self::DEFAULT_DATABASE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+
+    #[test]
+    fn env_overrides_file() {
+        let mut config = Config::from_toml(
+            r#"
+            discord_token = "from-file"
+            admin = "1234"
+            "#,
+        )
+        .unwrap();
+        config.apply_overrides(|key| (key == "DISCORD_TOKEN").then(|| "from-env".to_string()));
+
+        assert_eq!(config.discord_token().unwrap(), "from-env");
+        assert_eq!(config.admin().unwrap(), "1234");
+        assert_eq!(config.database(), Config::DEFAULT_DATABASE);
+    }
+
+    #[test]
+    fn missing_token() {
+        assert!(Config::default().discord_token().is_err());
+    }
+}
@@ -0,0 +1,670 @@
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serenity::all::{ChannelId, GuildId, MessageId, RoleId, UserId};
+use serenity::async_trait;
+
+use super::{
+    DeliveryStats, DeliveryStatus, GuildUpdate, OutboxEntry, Recipient, SentAlert, Store,
+    TursoCode, TursoGuild, TursoSubscription,
+};
+use crate::games::Game;
+use crate::scraper::ScrapedCode;
+
+/// Keeps everything in memory with the same semantics as [`super::TursoDb`].
+#[derive(Default)]
+pub struct MemoryStore {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    guilds: Vec<TursoGuild>,
+    /// Removed guilds and when they were removed
+    removed: Vec<(TursoGuild, DateTime<Utc>)>,
+    subscriptions: Vec<TursoSubscription>,
+    /// DM subscriptions in the order they were added
+    users: Vec<UserSubscription>,
+    codes: Vec<TursoCode>,
+    deliveries: Vec<Delivery>,
+    outbox: Vec<QueuedAlert>,
+    /// Outbox ids are never reused, like sqlite's autoincrement
+    last_outbox_id: i64,
+}
+
+struct UserSubscription {
+    user: UserId,
+    game: Game,
+    dm_channel: ChannelId,
+}
+
+struct Delivery {
+    recipient: Recipient,
+    code: i64,
+    status: DeliveryStatus,
+    outbox_id: Option<i64>,
+    channel: Option<ChannelId>,
+    message: Option<MessageId>,
+    sent_at: Option<DateTime<Utc>>,
+    shown_expired: bool,
+}
+
+struct QueuedAlert {
+    entry: OutboxEntry,
+    next_attempt_at: DateTime<Utc>,
+    dead: bool,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl State {
+    fn guild_mut(&mut self, guild: GuildId) -> Result<&mut TursoGuild> {
+        self.guilds
+            .iter_mut()
+            .find(|g| g.guild_id == guild)
+            .ok_or_else(|| anyhow!("Update did not succeed. Affected rows: 0"))
+    }
+
+    fn subscription_mut(&mut self, guild: GuildId, game: Game) -> &mut TursoSubscription {
+        let index = match self
+            .subscriptions
+            .iter()
+            .position(|sub| sub.guild_id == guild && sub.game == game)
+        {
+            Some(index) => index,
+            None => {
+                self.subscriptions.push(TursoSubscription {
+                    guild_id: guild,
+                    game,
+                    alert_channel: None,
+                    alert_role: None,
+                    enabled: 1,
+                });
+                self.subscriptions.len() - 1
+            }
+        };
+        &mut self.subscriptions[index]
+    }
+
+    fn delivered(&self, recipient: Recipient, code: i64) -> bool {
+        self.deliveries
+            .iter()
+            .any(|delivery| delivery.recipient == recipient && delivery.code == code)
+    }
+
+    /// Valid codes of `game` not delivered to `recipient` yet.
+    fn missing_codes(&self, recipient: Recipient, game: Game) -> Vec<TursoCode> {
+        self.codes
+            .iter()
+            .filter(|c| c.valid == 1 && c.game == game && !self.delivered(recipient, c.id))
+            .cloned()
+            .collect()
+    }
+
+    fn queued_alert(&mut self, id: i64) -> Result<&mut QueuedAlert> {
+        self.outbox
+            .iter_mut()
+            .find(|alert| alert.entry.id == id)
+            .ok_or_else(|| anyhow!("Unknown outbox entry {id}"))
+    }
+
+    fn set_delivery_status(&mut self, outbox_id: i64, status: DeliveryStatus) {
+        for delivery in self
+            .deliveries
+            .iter_mut()
+            .filter(|delivery| delivery.outbox_id == Some(outbox_id))
+        {
+            delivery.status = status;
+        }
+    }
+
+    /// Forgets the failed deliveries of the guild, only of `game` if given.
+    fn retry_failed(&mut self, guild: GuildId, game: Option<Game>) {
+        let failed: Vec<i64> = self
+            .deliveries
+            .iter()
+            .filter(|delivery| {
+                delivery.recipient == Recipient::Guild(guild)
+                    && delivery.status == DeliveryStatus::Failed
+                    && (game.is_none() || game == Some(self.code(delivery.code).game))
+            })
+            .map(|delivery| delivery.code)
+            .collect();
+        self.deliveries.retain(|delivery| {
+            delivery.recipient != Recipient::Guild(guild) || !failed.contains(&delivery.code)
+        });
+    }
+
+    fn code(&self, id: i64) -> &TursoCode {
+        self.codes
+            .iter()
+            .find(|code| code.id == id)
+            .expect("Deliveries reference known codes")
+    }
+
+    fn add_guild(&mut self, guild: GuildId) -> bool {
+        if self.guilds.iter().any(|g| g.guild_id == guild) {
+            return false;
+        }
+        if let Some(index) = self.removed.iter().position(|(g, _)| g.guild_id == guild) {
+            let (restored, _) = self.removed.remove(index);
+            self.guilds.push(restored);
+            self.guilds.sort_by_key(|g| g.id);
+            return true;
+        }
+        let id = self
+            .guilds
+            .iter()
+            .chain(self.removed.iter().map(|(g, _)| g))
+            .map(|g| g.id)
+            .max()
+            .unwrap_or(0)
+            + 1;
+        self.guilds.push(TursoGuild {
+            id,
+            guild_id: guild,
+            enabled: 1,
+            alert_channel: None,
+            alert_role: None,
+            manager_role: None,
+        });
+        true
+    }
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    async fn guilds(&self) -> Result<Vec<TursoGuild>> {
+        Ok(self.state.lock().unwrap().guilds.clone())
+    }
+
+    async fn guild(&self, guild: GuildId) -> Result<Option<TursoGuild>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.guilds.iter().find(|g| g.guild_id == guild).cloned())
+    }
+
+    async fn try_add_guild(&self, guild: GuildId) -> Result<bool> {
+        Ok(self.state.lock().unwrap().add_guild(guild))
+    }
+
+    async fn remove_guild(&self, guild: GuildId, at: DateTime<Utc>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(index) = state.guilds.iter().position(|g| g.guild_id == guild) {
+            let removed = state.guilds.remove(index);
+            state.removed.push((removed, at));
+        }
+        Ok(())
+    }
+
+    async fn purge_removed_guilds(&self, before: DateTime<Utc>) -> Result<Vec<GuildId>> {
+        let mut state = self.state.lock().unwrap();
+        let purged: Vec<GuildId> = state
+            .removed
+            .iter()
+            .filter(|(_, at)| *at < before)
+            .map(|(g, _)| g.guild_id)
+            .collect();
+        state.removed.retain(|(g, _)| !purged.contains(&g.guild_id));
+        state
+            .subscriptions
+            .retain(|sub| !purged.contains(&sub.guild_id));
+        state.deliveries.retain(|delivery| {
+            !matches!(delivery.recipient, Recipient::Guild(guild) if purged.contains(&guild))
+        });
+        state.outbox.retain(|alert| {
+            !matches!(alert.entry.update.recipient, Recipient::Guild(guild) if purged.contains(&guild))
+        });
+        Ok(purged)
+    }
+
+    async fn set_guild_state(&self, guild: GuildId, enabled: bool) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.add_guild(guild);
+        state.guild_mut(guild)?.enabled = enabled as i64;
+        if enabled {
+            state.retry_failed(guild, None);
+        }
+        Ok(())
+    }
+
+    async fn set_guild_alert_role(&self, guild: GuildId, role: Option<RoleId>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.guild_mut(guild)?.alert_role = role;
+        state.retry_failed(guild, None);
+        Ok(())
+    }
+
+    async fn set_guild_manager_role(&self, guild: GuildId, role: Option<RoleId>) -> Result<()> {
+        self.state.lock().unwrap().guild_mut(guild)?.manager_role = role;
+        Ok(())
+    }
+
+    async fn set_guild_alert_channel(
+        &self,
+        guild: GuildId,
+        channel: Option<ChannelId>,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.guild_mut(guild)?.alert_channel = channel;
+        state.retry_failed(guild, None);
+        Ok(())
+    }
+
+    async fn subscriptions(&self, guild: GuildId) -> Result<Vec<TursoSubscription>> {
+        let state = self.state.lock().unwrap();
+        let mut subscriptions: Vec<_> = state
+            .subscriptions
+            .iter()
+            .filter(|sub| sub.guild_id == guild)
+            .cloned()
+            .collect();
+        subscriptions.sort_by_key(|sub| sub.game.id());
+        Ok(subscriptions)
+    }
+
+    async fn set_subscription_state(
+        &self,
+        guild: GuildId,
+        game: Game,
+        enabled: bool,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.subscription_mut(guild, game).enabled = enabled as i64;
+        if enabled {
+            state.retry_failed(guild, Some(game));
+        }
+        Ok(())
+    }
+
+    async fn set_subscription_channel(
+        &self,
+        guild: GuildId,
+        game: Game,
+        channel: Option<ChannelId>,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.subscription_mut(guild, game).alert_channel = channel;
+        state.retry_failed(guild, Some(game));
+        Ok(())
+    }
+
+    async fn set_subscription_role(
+        &self,
+        guild: GuildId,
+        game: Game,
+        role: Option<RoleId>,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.subscription_mut(guild, game).alert_role = role;
+        state.retry_failed(guild, Some(game));
+        Ok(())
+    }
+
+    async fn forget_channel(&self, guild: GuildId, channel: ChannelId) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let mut used = false;
+        for setting in state
+            .guilds
+            .iter_mut()
+            .filter(|g| g.guild_id == guild)
+            .map(|g| &mut g.alert_channel)
+            .chain(
+                state
+                    .subscriptions
+                    .iter_mut()
+                    .filter(|sub| sub.guild_id == guild)
+                    .map(|sub| &mut sub.alert_channel),
+            )
+        {
+            if *setting == Some(channel) {
+                *setting = None;
+                used = true;
+            }
+        }
+        Ok(used)
+    }
+
+    async fn forget_role(&self, guild: GuildId, role: RoleId) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let mut used = false;
+        for setting in state
+            .guilds
+            .iter_mut()
+            .filter(|g| g.guild_id == guild)
+            .flat_map(|g| [&mut g.alert_role, &mut g.manager_role])
+            .chain(
+                state
+                    .subscriptions
+                    .iter_mut()
+                    .filter(|sub| sub.guild_id == guild)
+                    .map(|sub| &mut sub.alert_role),
+            )
+        {
+            if *setting == Some(role) {
+                *setting = None;
+                used = true;
+            }
+        }
+        Ok(used)
+    }
+
+    async fn record_codes(&self, game: Game, new_codes: &[ScrapedCode]) -> Result<()> {
+        if new_codes.is_empty() {
+            return Ok(());
+        }
+        let mut state = self.state.lock().unwrap();
+        for code in new_codes {
+            if let Some(known) = state
+                .codes
+                .iter_mut()
+                .find(|c| c.game == game && c.code == code.code)
+            {
+                known.valid = 1;
+                if !code.rewards.is_empty() {
+                    known.rewards = code.rewards.clone();
+                }
+                if code.expires_at.is_some() {
+                    known.expires_at = code.expires_at;
+                }
+                if known.source.is_none() {
+                    known.source = code.source.clone();
+                }
+            } else {
+                let id = state.codes.iter().map(|c| c.id).max().unwrap_or(0) + 1;
+                state.codes.push(TursoCode {
+                    id,
+                    code: code.code.clone(),
+                    valid: 1,
+                    rewards: code.rewards.clone(),
+                    expires_at: code.expires_at,
+                    first_seen: Utc::now(),
+                    game,
+                    source: code.source.clone(),
+                });
+            }
+        }
+        for known in state.codes.iter_mut().filter(|c| c.game == game) {
+            if !new_codes.iter().any(|code| code.code == known.code) {
+                known.valid = 0;
+            }
+        }
+        Ok(())
+    }
+
+    async fn expire_codes(&self, game: Game) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        for code in state.codes.iter_mut().filter(|code| code.game == game) {
+            code.valid = 0;
+        }
+        Ok(())
+    }
+
+    async fn valid_codes(&self) -> Result<Vec<TursoCode>> {
+        let state = self.state.lock().unwrap();
+        let mut codes: Vec<_> = state
+            .codes
+            .iter()
+            .filter(|code| code.is_valid())
+            .cloned()
+            .collect();
+        codes.sort_by_key(|code| (code.game.id(), code.id));
+        Ok(codes)
+    }
+
+    async fn pending_updates(&self) -> Result<Vec<GuildUpdate>> {
+        let state = self.state.lock().unwrap();
+        let mut updates = Vec::new();
+        for guild in state.guilds.iter().filter(|g| g.enabled == 1) {
+            let mut subscriptions: Vec<_> = state
+                .subscriptions
+                .iter()
+                .filter(|sub| sub.guild_id == guild.guild_id)
+                .cloned()
+                .collect();
+            if !subscriptions.iter().any(|sub| sub.game == Game::DEFAULT) {
+                subscriptions.push(TursoSubscription::implicit(guild.guild_id));
+            }
+            subscriptions.sort_by_key(|sub| sub.game.id());
+            for subscription in subscriptions.iter().filter(|sub| sub.enabled == 1) {
+                if subscription.channel(guild).is_none() {
+                    continue;
+                }
+                let recipient = Recipient::Guild(guild.guild_id);
+                let codes = state.missing_codes(recipient, subscription.game);
+                if !codes.is_empty() {
+                    updates.push(GuildUpdate {
+                        recipient,
+                        game: subscription.game,
+                        role: subscription.role(guild),
+                        chan: subscription.channel(guild),
+                        enabled: true,
+                        codes: Some(codes),
+                    });
+                }
+            }
+        }
+
+        let mut users: Vec<&UserSubscription> = state.users.iter().collect();
+        users.sort_by_key(|sub| (sub.user.to_string(), sub.game.id()));
+        for subscription in users {
+            let recipient = Recipient::User(subscription.user);
+            let codes = state.missing_codes(recipient, subscription.game);
+            if !codes.is_empty() {
+                updates.push(GuildUpdate {
+                    recipient,
+                    game: subscription.game,
+                    role: None,
+                    chan: Some(subscription.dm_channel),
+                    enabled: true,
+                    codes: Some(codes),
+                });
+            }
+        }
+        Ok(updates)
+    }
+
+    async fn enqueue(&self, update: &GuildUpdate) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.last_outbox_id += 1;
+        let id = state.last_outbox_id;
+        for code in update.codes.iter().flatten() {
+            state.deliveries.retain(|delivery| {
+                !(delivery.recipient == update.recipient && delivery.code == code.id)
+            });
+            state.deliveries.push(Delivery {
+                recipient: update.recipient,
+                code: code.id,
+                status: DeliveryStatus::Queued,
+                outbox_id: Some(id),
+                channel: None,
+                message: None,
+                sent_at: None,
+                shown_expired: false,
+            });
+        }
+        state.outbox.push(QueuedAlert {
+            entry: OutboxEntry {
+                id,
+                attempts: 0,
+                update: update.clone(),
+            },
+            next_attempt_at: Utc::now(),
+            dead: false,
+        });
+        Ok(())
+    }
+
+    async fn due_outbox(&self, now: DateTime<Utc>) -> Result<Vec<OutboxEntry>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .outbox
+            .iter()
+            .filter(|alert| !alert.dead && alert.next_attempt_at <= now)
+            .map(|alert| alert.entry.clone())
+            .collect())
+    }
+
+    async fn complete_outbox(&self, id: i64, message: Option<MessageId>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let channel = state.queued_alert(id)?.entry.update.chan;
+        state.set_delivery_status(id, DeliveryStatus::Sent);
+        for delivery in state
+            .deliveries
+            .iter_mut()
+            .filter(|delivery| delivery.outbox_id == Some(id))
+        {
+            delivery.channel = channel;
+            delivery.message = message;
+            delivery.sent_at = Some(Utc::now());
+            delivery.outbox_id = None;
+        }
+        state.outbox.retain(|alert| alert.entry.id != id);
+        Ok(())
+    }
+
+    async fn retry_outbox(
+        &self,
+        id: i64,
+        next_attempt_at: DateTime<Utc>,
+        _error: &str,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let alert = state.queued_alert(id)?;
+        alert.entry.attempts += 1;
+        alert.next_attempt_at = next_attempt_at;
+        Ok(())
+    }
+
+    async fn dead_letter_outbox(&self, id: i64, _error: &str) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let alert = state.queued_alert(id)?;
+        alert.entry.attempts += 1;
+        alert.dead = true;
+        state.set_delivery_status(id, DeliveryStatus::Failed);
+        Ok(())
+    }
+
+    async fn delivery_stats(&self, guild: GuildId) -> Result<DeliveryStats> {
+        let state = self.state.lock().unwrap();
+        let deliveries: Vec<&Delivery> = state
+            .deliveries
+            .iter()
+            .filter(|delivery| delivery.recipient == Recipient::Guild(guild))
+            .collect();
+        let count = |status| {
+            deliveries
+                .iter()
+                .filter(|delivery| delivery.status == status)
+                .count() as i64
+        };
+        Ok(DeliveryStats {
+            last_sent: deliveries
+                .iter()
+                .filter(|delivery| delivery.status == DeliveryStatus::Sent)
+                .max_by_key(|delivery| (delivery.sent_at, delivery.code))
+                .map(|delivery| (state.code(delivery.code).clone(), delivery.sent_at)),
+            queued: count(DeliveryStatus::Queued),
+            failed: count(DeliveryStatus::Failed),
+        })
+    }
+
+    async fn subscribe_user(
+        &self,
+        user: UserId,
+        game: Game,
+        dm_channel: ChannelId,
+    ) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        if state
+            .users
+            .iter()
+            .any(|sub| sub.user == user && sub.game == game)
+        {
+            return Ok(false);
+        }
+        state.users.push(UserSubscription {
+            user,
+            game,
+            dm_channel,
+        });
+        Ok(true)
+    }
+
+    async fn unsubscribe_user(&self, user: UserId, game: Option<Game>) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        let before = state.users.len();
+        state
+            .users
+            .retain(|sub| !(sub.user == user && (game.is_none() || game == Some(sub.game))));
+        if state.users.iter().all(|sub| sub.user != user) {
+            state
+                .deliveries
+                .retain(|delivery| delivery.recipient != Recipient::User(user));
+            state
+                .outbox
+                .retain(|alert| alert.entry.update.recipient != Recipient::User(user));
+        }
+        Ok(state.users.len() != before)
+    }
+
+    async fn stale_alerts(&self) -> Result<Vec<SentAlert>> {
+        let state = self.state.lock().unwrap();
+        let mut messages: Vec<(Recipient, ChannelId, MessageId)> = state
+            .deliveries
+            .iter()
+            .filter(|delivery| {
+                // Shown as expired while valid or the other way around
+                delivery.status == DeliveryStatus::Sent
+                    && delivery.shown_expired == state.code(delivery.code).is_valid()
+            })
+            .filter_map(|delivery| Some((delivery.recipient, delivery.channel?, delivery.message?)))
+            .collect();
+        messages.sort_by_key(|(_, _, message)| message.to_string());
+        messages.dedup();
+
+        Ok(messages
+            .into_iter()
+            .map(|(recipient, channel, message)| {
+                let mut codes: Vec<TursoCode> = state
+                    .deliveries
+                    .iter()
+                    .filter(|delivery| delivery.message == Some(message))
+                    .map(|delivery| state.code(delivery.code).clone())
+                    .collect();
+                codes.sort_by_key(|code| code.id);
+                SentAlert {
+                    recipient,
+                    channel,
+                    message,
+                    game: codes[0].game,
+                    codes,
+                }
+            })
+            .collect())
+    }
+
+    async fn mark_alert_updated(&self, message: MessageId) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let expired: Vec<i64> = state
+            .codes
+            .iter()
+            .filter(|code| !code.is_valid())
+            .map(|code| code.id)
+            .collect();
+        for delivery in state
+            .deliveries
+            .iter_mut()
+            .filter(|delivery| delivery.message == Some(message))
+        {
+            delivery.shown_expired = expired.contains(&delivery.code);
+        }
+        Ok(())
+    }
+}
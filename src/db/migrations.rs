@@ -0,0 +1,256 @@
+use anyhow::{anyhow, Result};
+use libsql::{params, Connection, ValueType};
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// Schema changes in the order they are applied. Released migrations must not
+/// be edited, add a new one instead.
+pub const MIGRATIONS: [Migration; 10] = [
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("../../sql/migrations/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "code_details",
+        sql: include_str!("../../sql/migrations/0002_code_details.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "games",
+        sql: include_str!("../../sql/migrations/0003_games.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "deliveries",
+        sql: include_str!("../../sql/migrations/0004_deliveries.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "outbox",
+        sql: include_str!("../../sql/migrations/0005_outbox.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "code_source",
+        sql: include_str!("../../sql/migrations/0006_code_source.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "alert_expiry",
+        sql: include_str!("../../sql/migrations/0007_alert_expiry.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "manager_role",
+        sql: include_str!("../../sql/migrations/0008_manager_role.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "users",
+        sql: include_str!("../../sql/migrations/0009_users.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "guild_removal",
+        sql: include_str!("../../sql/migrations/0010_guild_removal.sql"),
+    },
+];
+
+/// Brings the schema up to the latest version.
+pub async fn run(client: &Connection) -> Result<()> {
+    apply(client, &MIGRATIONS).await
+}
+
+/// Applies every migration newer than the current schema version in a single
+/// transaction. Nothing is applied if any of them fails.
+async fn apply(client: &Connection, migrations: &[Migration]) -> Result<()> {
+    client
+        .execute(
+            "CREATE TABLE IF NOT EXISTS schema_version (version integer primary key, name text not null, applied_at text not null default CURRENT_TIMESTAMP);",
+            (),
+        )
+        .await?;
+
+    let current = version(client).await?;
+    let latest = migrations.iter().map(|m| m.version).max().unwrap_or(0);
+    if current > latest {
+        return Err(anyhow!(
+            "Database schema version {current} is newer than the latest known version {latest}"
+        ));
+    }
+    if current == latest {
+        info!(version = current, "Database schema is up to date");
+        return Ok(());
+    }
+
+    // A libsql `Transaction` is not `Send`, so it could not be held across
+    // the awaits of the Shuttle entry point
+    client.execute_batch("BEGIN;").await?;
+    for migration in migrations.iter().filter(|m| m.version > current) {
+        info!(
+            version = migration.version,
+            name = migration.name,
+            "Applying migration"
+        );
+        let applied = async {
+            client.execute_batch(migration.sql).await?;
+            client
+                .execute(
+                    "INSERT INTO schema_version (version, name) VALUES (?1, ?2);",
+                    params![migration.version, migration.name],
+                )
+                .await
+        }
+        .await;
+        if let Err(err) = applied {
+            if let Err(rollback) = client.execute_batch("ROLLBACK;").await {
+                error!(
+                    reason = rollback.to_string(),
+                    "Could not roll back migrations"
+                );
+            }
+            return Err(anyhow!(
+                "Failed to apply migration {} ({}): {}",
+                migration.version,
+                migration.name,
+                err
+            ));
+        }
+    }
+    client.execute_batch("COMMIT;").await?;
+    info!(from = current, to = latest, "Migrated database schema");
+    Ok(())
+}
+
+/// The version of the last applied migration, 0 for a new database.
+pub async fn version(client: &Connection) -> Result<i64> {
+    let mut rows = client
+        .query("SELECT MAX(version) FROM schema_version;", ())
+        .await?;
+    match rows.next()? {
+        Some(row) if matches!(row.column_type(0)?, ValueType::Integer) => Ok(row.get(0)?),
+        _ => Ok(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libsql::{Connection, Database};
+
+    use super::{apply, run, version, Migration, MIGRATIONS};
+
+    fn connect() -> Connection {
+        Database::open(":memory:").unwrap().connect().unwrap()
+    }
+
+    async fn count(client: &Connection, table: &str) -> i64 {
+        let mut rows = client
+            .query(&format!("SELECT COUNT(*) FROM {table};"), ())
+            .await
+            .unwrap();
+        rows.next().unwrap().unwrap().get(0).unwrap()
+    }
+
+    #[tokio::test]
+    async fn migrates_new_database() {
+        let client = connect();
+        run(&client).await.unwrap();
+        assert_eq!(version(&client).await.unwrap(), MIGRATIONS.len() as i64);
+
+        // Running again is a no-op
+        run(&client).await.unwrap();
+        assert_eq!(
+            count(&client, "schema_version").await,
+            MIGRATIONS.len() as i64
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_data_of_unversioned_database() {
+        let client = connect();
+        client.execute_batch(MIGRATIONS[0].sql).await.unwrap();
+        client
+            .execute("INSERT INTO codes (code, valid) VALUES ('OLDCODE', 1);", ())
+            .await
+            .unwrap();
+
+        run(&client).await.unwrap();
+
+        let mut rows = client
+            .query("SELECT code, rewards, game FROM codes;", ())
+            .await
+            .unwrap();
+        let row = rows.next().unwrap().unwrap();
+        assert_eq!(row.get::<String>(0).unwrap(), "OLDCODE");
+        assert_eq!(row.get::<String>(1).unwrap(), "[]");
+        assert_eq!(row.get::<i64>(2).unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn watermark_becomes_deliveries() {
+        let client = connect();
+        client.execute_batch(MIGRATIONS[0].sql).await.unwrap();
+        client
+            .execute_batch(
+                "INSERT INTO codes (code, valid) VALUES ('SENT', 1), ('NEW', 1);
+                INSERT INTO guilds (guild_id, last_code) VALUES ('1', 1);",
+            )
+            .await
+            .unwrap();
+
+        run(&client).await.unwrap();
+
+        let mut rows = client
+            .query("SELECT guild_id, code, status FROM deliveries;", ())
+            .await
+            .unwrap();
+        let row = rows.next().unwrap().unwrap();
+        assert_eq!(row.get::<String>(0).unwrap(), "1");
+        assert_eq!(row.get::<i64>(1).unwrap(), 1);
+        assert_eq!(row.get::<String>(2).unwrap(), "sent");
+        assert!(rows.next().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn failed_migration_is_rolled_back() {
+        let client = connect();
+        let migrations = [
+            Migration {
+                version: 1,
+                name: "works",
+                sql: "CREATE TABLE first (id integer);",
+            },
+            Migration {
+                version: 2,
+                name: "broken",
+                sql: "CREATE TABLE second (id integer); NOT SQL;",
+            },
+        ];
+
+        assert!(apply(&client, &migrations).await.is_err());
+        assert_eq!(version(&client).await.unwrap(), 0);
+        assert!(client.query("SELECT * FROM first;", ()).await.is_err());
+        assert!(client.query("SELECT * FROM second;", ()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn refuses_newer_schema() {
+        let client = connect();
+        run(&client).await.unwrap();
+        client
+            .execute(
+                "INSERT INTO schema_version (version, name) VALUES (99, 'future');",
+                (),
+            )
+            .await
+            .unwrap();
+
+        assert!(run(&client).await.is_err());
+    }
+}
@@ -1,22 +1,31 @@
-use std::{i64, sync::Arc};
-
 use anyhow::{anyhow, Result};
-use libsql::{params, Connection, Row, ValueType};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use libsql::{Row, ValueType};
 use serde::{Deserialize, Serialize};
-use serenity::all::{
-    ChannelId, Context, CreateMessage, Guild, GuildChannel, GuildId, PartialGuild, RoleId,
-    UnavailableGuild,
-};
-use std::collections::HashMap;
+use serenity::all::{ChannelId, GuildId, MessageId, RoleId, UnavailableGuild, UserId};
+use serenity::async_trait;
+
+use crate::games::Game;
+use crate::scraper::ScrapedCode;
+
+#[cfg(test)]
+mod memory;
+pub mod migrations;
+mod turso;
+
+#[cfg(test)]
+pub use memory::MemoryStore;
+pub use turso::TursoDb;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TursoGuild {
     pub id: i64,
     pub guild_id: GuildId,
     pub enabled: i64,
-    pub last_code: i64,
     pub alert_channel: Option<ChannelId>,
     pub alert_role: Option<RoleId>,
+    /// Members with this role may configure the bot without Manage Server
+    pub manager_role: Option<RoleId>,
 }
 
 impl TursoGuild {
@@ -24,9 +33,9 @@ impl TursoGuild {
         let id: i64;
         let guild_id: GuildId;
         let enabled: i64;
-        let last_code: i64;
         let alert_channel: Option<ChannelId>;
         let alert_role: Option<RoleId>;
+        let manager_role: Option<RoleId>;
 
         if let Some("id") = row.column_name(0) {
             if let Ok(ValueType::Integer) = row.column_type(0) {
@@ -76,9 +85,11 @@ impl TursoGuild {
             ));
         }
 
-        if let Some("last_code") = row.column_name(3) {
-            if let Ok(ValueType::Integer) = row.column_type(3) {
-                last_code = row.get(3)?;
+        if let Some("alert_channel") = row.column_name(3) {
+            if let Ok(ValueType::Text) = row.column_type(3) {
+                alert_channel = Some(ChannelId::new(row.get::<String>(3)?.parse::<u64>()?));
+            } else if let Ok(ValueType::Null) = row.column_type(3) {
+                alert_channel = None;
             } else {
                 return Err(anyhow!(
                     "Expected field 3 to be of type Integer. Was {:?}",
@@ -87,43 +98,43 @@ impl TursoGuild {
             }
         } else {
             return Err(anyhow!(
-                "Expected field 3 to be named 'last_code'. Was {:?}",
+                "Expected field 3 to be named 'alert_channel'. Was {:?}",
                 row.column_name(3)
             ));
         }
 
-        if let Some("alert_channel") = row.column_name(4) {
+        if let Some("alert_role") = row.column_name(4) {
             if let Ok(ValueType::Text) = row.column_type(4) {
-                alert_channel = Some(ChannelId::new(row.get::<String>(4)?.parse::<u64>()?));
+                alert_role = Some(RoleId::new(row.get::<String>(4)?.parse::<u64>()?));
             } else if let Ok(ValueType::Null) = row.column_type(4) {
-                alert_channel = None;
+                alert_role = None;
             } else {
                 return Err(anyhow!(
-                    "Expected field 4 to be of type Integer. Was {:?}",
+                    "Expected field 4 to be of type Integer or Null. Was {:?}",
                     row.column_type(4)
                 ));
             }
         } else {
             return Err(anyhow!(
-                "Expected field 4 to be named 'alert_channel'. Was {:?}",
+                "Expected field 4 to be named 'alert_role'. Was {:?}",
                 row.column_name(4)
             ));
         }
 
-        if let Some("alert_role") = row.column_name(5) {
+        if let Some("manager_role") = row.column_name(5) {
             if let Ok(ValueType::Text) = row.column_type(5) {
-                alert_role = Some(RoleId::new(row.get::<String>(5)?.parse::<u64>()?));
+                manager_role = Some(RoleId::new(row.get::<String>(5)?.parse::<u64>()?));
             } else if let Ok(ValueType::Null) = row.column_type(5) {
-                alert_role = None;
+                manager_role = None;
             } else {
                 return Err(anyhow!(
-                    "Expected field 5 to be of type Integer or Null. Was {:?}",
+                    "Expected field 5 to be of type Text or Null. Was {:?}",
                     row.column_type(5)
                 ));
             }
         } else {
             return Err(anyhow!(
-                "Expected field 5 to be named 'alert_role'. Was {:?}",
+                "Expected field 5 to be named 'manager_role'. Was {:?}",
                 row.column_name(5)
             ));
         }
@@ -132,25 +143,49 @@ impl TursoGuild {
             id,
             guild_id,
             enabled,
-            last_code,
             alert_channel,
             alert_role,
+            manager_role,
         })
     }
 }
 
+/// Parses timestamps written by us (RFC 3339) and by sqlite's `CURRENT_TIMESTAMP`.
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+    Ok(NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S")?.and_utc())
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TursoCode {
     pub id: i64,
     pub code: String,
     pub valid: i64,
+    pub rewards: Vec<String>,
+    pub expires_at: Option<NaiveDate>,
+    pub first_seen: DateTime<Utc>,
+    pub game: Game,
+    /// Source that first reported the code, unknown for codes recorded
+    /// before sources were tracked
+    pub source: Option<String>,
 }
 
 impl TursoCode {
+    pub fn is_valid(&self) -> bool {
+        self.valid == 1
+    }
+
     pub fn from_row(row: Row) -> Result<Self> {
         let id: i64;
         let code: String;
         let valid: i64;
+        let rewards: Vec<String>;
+        let expires_at: Option<NaiveDate>;
+        let first_seen: DateTime<Utc>;
+        let game: Game;
+        let source: Option<String>;
 
         if let Some("id") = row.column_name(0) {
             if let Ok(ValueType::Integer) = row.column_type(0) {
@@ -200,420 +235,1193 @@ impl TursoCode {
             ));
         }
 
-        Ok(Self { id, code, valid })
-    }
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GuildUpdate {
-    pub id: GuildId,
-    pub role: Option<RoleId>,
-    pub chan: Option<ChannelId>,
-    pub codes: Option<Vec<TursoCode>>,
-    pub enabled: bool,
-}
+        if let Some("rewards") = row.column_name(3) {
+            if let Ok(ValueType::Text) = row.column_type(3) {
+                rewards = serde_json::from_str(&row.get::<String>(3)?)?;
+            } else {
+                return Err(anyhow!(
+                    "Expected field 3 to be of type Text. Was {:?}",
+                    row.column_type(3)
+                ));
+            }
+        } else {
+            return Err(anyhow!(
+                "Expected field 3 to be named 'rewards'. Was {:?}",
+                row.column_name(3)
+            ));
+        }
 
-impl GuildUpdate {
-    pub fn for_guild(guild: TursoGuild, codes: Option<Vec<TursoCode>>) -> Self {
-        Self {
-            id: guild.guild_id,
-            role: guild.alert_role,
-            chan: guild.alert_channel,
-            enabled: guild.enabled == 1,
-            codes,
+        if let Some("expires_at") = row.column_name(4) {
+            if let Ok(ValueType::Text) = row.column_type(4) {
+                expires_at = Some(row.get::<String>(4)?.parse::<NaiveDate>()?);
+            } else if let Ok(ValueType::Null) = row.column_type(4) {
+                expires_at = None;
+            } else {
+                return Err(anyhow!(
+                    "Expected field 4 to be of type Text or Null. Was {:?}",
+                    row.column_type(4)
+                ));
+            }
+        } else {
+            return Err(anyhow!(
+                "Expected field 4 to be named 'expires_at'. Was {:?}",
+                row.column_name(4)
+            ));
         }
-    }
 
-    pub fn has_codes(&self) -> bool {
-        self.codes.as_ref().map_or(false, |codes| !codes.is_empty())
-    }
-}
+        if let Some("first_seen") = row.column_name(5) {
+            if let Ok(ValueType::Text) = row.column_type(5) {
+                first_seen = parse_timestamp(&row.get::<String>(5)?)?;
+            } else {
+                return Err(anyhow!(
+                    "Expected field 5 to be of type Text. Was {:?}",
+                    row.column_type(5)
+                ));
+            }
+        } else {
+            return Err(anyhow!(
+                "Expected field 5 to be named 'first_seen'. Was {:?}",
+                row.column_name(5)
+            ));
+        }
 
-pub struct TursoDb {
-    client: Arc<Connection>,
-}
+        if let Some("game") = row.column_name(6) {
+            if let Ok(ValueType::Integer) = row.column_type(6) {
+                let game_id: i64 = row.get(6)?;
+                game = Game::from_id(game_id).ok_or_else(|| anyhow!("Unknown game {game_id}"))?;
+            } else {
+                return Err(anyhow!(
+                    "Expected field 6 to be of type Integer. Was {:?}",
+                    row.column_type(6)
+                ));
+            }
+        } else {
+            return Err(anyhow!(
+                "Expected field 6 to be named 'game'. Was {:?}",
+                row.column_name(6)
+            ));
+        }
 
-impl TursoDb {
-    async fn guilds(&self) -> Result<Vec<TursoGuild>> {
-        let mut rows = self.client.query("SELECT * FROM guilds;", ()).await?;
-        let mut guilds = Vec::new();
-        while let Some(row) = rows.next()? {
-            guilds.push(TursoGuild::from_row(row)?);
+        if let Some("source") = row.column_name(7) {
+            if let Ok(ValueType::Text) = row.column_type(7) {
+                source = Some(row.get(7)?);
+            } else if let Ok(ValueType::Null) = row.column_type(7) {
+                source = None;
+            } else {
+                return Err(anyhow!(
+                    "Expected field 7 to be of type Text or Null. Was {:?}",
+                    row.column_type(7)
+                ));
+            }
+        } else {
+            return Err(anyhow!(
+                "Expected field 7 to be named 'source'. Was {:?}",
+                row.column_name(7)
+            ));
         }
-        Ok(guilds)
-    }
 
-    pub async fn new(client: Arc<Connection>) -> Result<Self> {
         Ok(Self {
-            client: client.clone(),
+            id,
+            code,
+            valid,
+            rewards,
+            expires_at,
+            first_seen,
+            game,
+            source,
         })
     }
+}
 
-    async fn invalidate_codes(&self, new_codes: &Vec<String>) -> Result<()> {
-        let code_str = new_codes
-            .iter()
-            .map(|code| format!("'{code}'"))
-            .collect::<Vec<_>>()
-            .join(",");
-        let q = format!("UPDATE codes SET valid = 0 WHERE code NOT IN ({code_str})");
-        self.client.execute(q.as_str(), ()).await?;
-        Ok(())
-    }
+/// Alerts of one game for one guild. Channel and role fall back to the guild's
+/// when not set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TursoSubscription {
+    pub guild_id: GuildId,
+    pub game: Game,
+    pub alert_channel: Option<ChannelId>,
+    pub alert_role: Option<RoleId>,
+    pub enabled: i64,
+}
 
-    pub async fn send_to_all_guilds(&self, message: String, ctx: &Context) -> Result<()> {
-        let all_guilds = self.guilds().await?;
-
-        for guild in all_guilds {
-            if let Some(channel_id) = guild.alert_channel {
-                if let Ok(guild) = Self::get_guild(&guild.guild_id, ctx).await {
-                    if let Some((_, channel)) = guild
-                        .channels(&ctx.http)
-                        .await?
-                        .iter()
-                        .find(|(&id, _)| id == channel_id)
-                    {
-                        channel
-                            .send_message(&ctx.http, CreateMessage::new().content(&message))
-                            .await?;
-                    }
-                }
-            }
+impl TursoSubscription {
+    /// The subscription every guild has to the default game until it
+    /// configures it.
+    pub fn implicit(guild_id: GuildId) -> Self {
+        Self {
+            guild_id,
+            game: Game::DEFAULT,
+            alert_channel: None,
+            alert_role: None,
+            enabled: 1,
         }
-
-        Ok(())
     }
 
-    pub async fn set_codes_sent(
-        &self,
-        guild: GuildId,
-        codes: Option<Vec<TursoCode>>,
-    ) -> Result<()> {
-        if let Some(codes) = codes {
-            let last_inserted = codes
-                .iter()
-                .max_by(|x, y| x.id.cmp(&y.id))
-                .map_or(0, |code| code.id);
-            let res = self
-                .client
-                .execute(
-                    "UPDATE guilds SET last_code = ?1 WHERE guild_id = ?2",
-                    params![last_inserted, guild.to_string()],
-                )
-                .await?;
-            if res != 1 {
+    pub fn from_row(row: Row) -> Result<Self> {
+        let guild_id: GuildId;
+        let game: Game;
+        let alert_channel: Option<ChannelId>;
+        let alert_role: Option<RoleId>;
+        let enabled: i64;
+
+        if let Some("guild_id") = row.column_name(0) {
+            if let Ok(ValueType::Text) = row.column_type(0) {
+                guild_id = GuildId::new(row.get::<String>(0)?.parse::<u64>()?);
+            } else {
                 return Err(anyhow!(
-                    "Could not update last_code. Affected rows: {}",
-                    res
+                    "Expected field 0 to be of type Text. Was {:?}",
+                    row.column_type(0)
                 ));
             }
+        } else {
+            return Err(anyhow!(
+                "Expected field 0 to be named 'guild_id'. Was {:?}",
+                row.column_name(0)
+            ));
         }
-        Ok(())
-    }
 
-    pub async fn diff_guild_codes(
-        &self,
-        new_codes: &Vec<String>,
-        ctx: &Context,
-    ) -> Result<HashMap<GuildId, GuildUpdate>> {
-        for code in new_codes {
-            let mut exist = self
-                .client
-                .query("SELECT * FROM codes WHERE code = ?1;", [code.as_str()])
-                .await?;
-            if let None = exist.next()? {
-                self.client
-                    .execute(
-                        "INSERT INTO codes (id, code, valid) VALUES (NULL, ?1, 1);",
-                        [code.as_str()],
-                    )
-                    .await?;
+        if let Some("game") = row.column_name(1) {
+            if let Ok(ValueType::Integer) = row.column_type(1) {
+                let game_id: i64 = row.get(1)?;
+                game = Game::from_id(game_id).ok_or_else(|| anyhow!("Unknown game {game_id}"))?;
+            } else {
+                return Err(anyhow!(
+                    "Expected field 1 to be of type Integer. Was {:?}",
+                    row.column_type(1)
+                ));
             }
+        } else {
+            return Err(anyhow!(
+                "Expected field 1 to be named 'game'. Was {:?}",
+                row.column_name(1)
+            ));
         }
-        self.invalidate_codes(new_codes).await?;
-        let mut new_codes = HashMap::new();
-        for guild in self.guilds().await? {
-            if guild.enabled == 0 {
-                warn!(guild=?guild.guild_id, "Skipping disabled guild");
-                continue;
-            }
 
-            if let Some(_) = self.validate_guild(&guild, ctx).await? {
-                warn!(guild=?guild.guild_id, "Disabling invalid guild");
-                if let Err(err) = self.set_guild_state(guild.guild_id, false).await {
-                    error!(reason = err.to_string(), "Could not disable invalid guild");
-                }
-                continue;
+        if let Some("alert_channel") = row.column_name(2) {
+            if let Ok(ValueType::Text) = row.column_type(2) {
+                alert_channel = Some(ChannelId::new(row.get::<String>(2)?.parse::<u64>()?));
+            } else if let Ok(ValueType::Null) = row.column_type(2) {
+                alert_channel = None;
+            } else {
+                return Err(anyhow!(
+                    "Expected field 2 to be of type Text or Null. Was {:?}",
+                    row.column_type(2)
+                ));
             }
+        } else {
+            return Err(anyhow!(
+                "Expected field 2 to be named 'alert_channel'. Was {:?}",
+                row.column_name(2)
+            ));
+        }
 
-            let mut rows = self.client.query("SELECT * FROM codes WHERE id > (SELECT last_code FROM guilds WHERE guild_id = ?1) AND valid = 1", [guild.guild_id.to_string()]).await?;
-            let mut codes = Vec::new();
-            let guild_id = guild.guild_id;
-            while let Some(row) = rows.next()? {
-                codes.push(TursoCode::from_row(row)?);
+        if let Some("alert_role") = row.column_name(3) {
+            if let Ok(ValueType::Text) = row.column_type(3) {
+                alert_role = Some(RoleId::new(row.get::<String>(3)?.parse::<u64>()?));
+            } else if let Ok(ValueType::Null) = row.column_type(3) {
+                alert_role = None;
+            } else {
+                return Err(anyhow!(
+                    "Expected field 3 to be of type Text or Null. Was {:?}",
+                    row.column_type(3)
+                ));
             }
-            if codes.is_empty() {
-                new_codes.insert(guild_id, GuildUpdate::for_guild(guild, None));
-                return Ok(new_codes);
+        } else {
+            return Err(anyhow!(
+                "Expected field 3 to be named 'alert_role'. Was {:?}",
+                row.column_name(3)
+            ));
+        }
+
+        if let Some("enabled") = row.column_name(4) {
+            if let Ok(ValueType::Integer) = row.column_type(4) {
+                enabled = row.get(4)?;
             } else {
-                new_codes.insert(guild_id, GuildUpdate::for_guild(guild, Some(codes)));
+                return Err(anyhow!(
+                    "Expected field 4 to be of type Integer. Was {:?}",
+                    row.column_type(4)
+                ));
             }
+        } else {
+            return Err(anyhow!(
+                "Expected field 4 to be named 'enabled'. Was {:?}",
+                row.column_name(4)
+            ));
         }
 
-        Ok(new_codes)
+        Ok(Self {
+            guild_id,
+            game,
+            alert_channel,
+            alert_role,
+            enabled,
+        })
     }
 
-    pub async fn set_guild_state(&self, guild: GuildId, enabled: bool) -> Result<()> {
-        let mut exists = self
-            .client
-            .query(
-                "SELECT * FROM guilds WHERE guild_id = ?1",
-                [guild.to_string()],
-            )
-            .await?;
-        if let None = exists.next()? {
-            if self.try_add_guild(guild).await? {
-                info!(id=?guild, "Discovered new guild!. Added to db");
-            }
-        }
-        let res = self
-            .client
-            .execute(
-                "UPDATE guilds SET enabled = ?1 WHERE guild_id = ?2;",
-                params![enabled as i64, guild.to_string()],
-            )
-            .await?;
-        if res != 1 {
-            return Err(anyhow!("Update did not succeed. Affected rows: {}", res));
-        }
-        Ok(())
+    pub fn channel(&self, guild: &TursoGuild) -> Option<ChannelId> {
+        self.alert_channel.or(guild.alert_channel)
     }
 
-    pub async fn guild_alert_role(&self, guild: GuildId) -> Result<Option<RoleId>> {
-        let guilds = self.guilds().await?;
-        let guild_info = guilds.iter().find(|g| g.guild_id == guild);
-        return if let Some(g) = guild_info {
-            Ok(g.alert_role)
-        } else {
-            Ok(None)
-        };
+    pub fn role(&self, guild: &TursoGuild) -> Option<RoleId> {
+        self.alert_role.or(guild.alert_role)
     }
+}
+
+/// State of sending a code to a guild.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeliveryStatus {
+    Queued,
+    Sent,
+    Failed,
+}
 
-    pub async fn set_guild_alert_role(&self, guild: GuildId, role: Option<RoleId>) -> Result<()> {
-        let res = self
-            .client
-            .execute(
-                "UPDATE guilds SET alert_role = ?1 WHERE guild_id = ?2",
-                params![role.map(|id| i64::from(id)), guild.to_string()],
-            )
-            .await?;
-        if res != 1 {
-            return Err(anyhow!("Update did not succeed. Affected rows: {}", res));
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Queued => "queued",
+            DeliveryStatus::Sent => "sent",
+            DeliveryStatus::Failed => "failed",
         }
-        Ok(())
     }
+}
 
-    #[allow(dead_code)] // For completeness
-    pub async fn guild_alert_channel(&self, guild: GuildId) -> Result<Option<ChannelId>> {
-        let guilds = self.guilds().await?;
-        let guild_info = guilds.iter().find(|g| g.guild_id == guild);
-        return if let Some(g) = guild_info {
-            Ok(g.alert_channel)
-        } else {
-            Ok(None)
-        };
+/// An alert waiting in the outbox.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    pub id: i64,
+    /// Failed attempts so far
+    pub attempts: i64,
+    pub update: GuildUpdate,
+}
+
+/// What happened to the alerts of one guild.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeliveryStats {
+    /// The most recently sent code and when it was sent, if known
+    pub last_sent: Option<(TursoCode, Option<DateTime<Utc>>)>,
+    /// Codes waiting in the outbox
+    pub queued: i64,
+    /// Codes that could not be sent
+    pub failed: i64,
+}
+
+/// Who an alert is sent to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Recipient {
+    Guild(GuildId),
+    /// A user subscribed to alerts by DM
+    User(UserId),
+}
+
+/// A posted alert whose codes changed validity since it was last rendered.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SentAlert {
+    pub recipient: Recipient,
+    pub channel: ChannelId,
+    pub message: MessageId,
+    pub game: Game,
+    /// Every code of the alert, expired ones included
+    pub codes: Vec<TursoCode>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GuildUpdate {
+    pub recipient: Recipient,
+    pub game: Game,
+    pub role: Option<RoleId>,
+    pub chan: Option<ChannelId>,
+    pub codes: Option<Vec<TursoCode>>,
+    pub enabled: bool,
+}
+
+impl GuildUpdate {
+    pub fn has_codes(&self) -> bool {
+        self.codes.as_ref().map_or(false, |codes| !codes.is_empty())
     }
+}
+
+/// Persistence of guild configuration, known codes and what was delivered where.
+///
+/// [`TursoDb`] is the backend used by the bot, `MemoryStore` keeps everything in
+/// memory for tests.
+#[async_trait]
+pub trait Store: Send + Sync {
+    async fn guilds(&self) -> Result<Vec<TursoGuild>>;
+
+    async fn guild(&self, guild: GuildId) -> Result<Option<TursoGuild>>;
+
+    /// Adds the guild if it is not known yet, or restores it if it was
+    /// removed. Returns whether it was added or restored.
+    async fn try_add_guild(&self, guild: GuildId) -> Result<bool>;
+
+    /// Marks the guild as removed at `at`. Removed guilds are hidden and get no
+    /// alerts, but keep their settings until they are purged.
+    async fn remove_guild(&self, guild: GuildId, at: DateTime<Utc>) -> Result<()>;
+
+    /// Deletes everything stored about guilds removed before `before`.
+    /// Returns the purged guilds.
+    async fn purge_removed_guilds(&self, before: DateTime<Utc>) -> Result<Vec<GuildId>>;
+
+    /// Enables or disables the guild, adding it first if it is not known yet.
+    ///
+    /// Enabling a guild or changing its alert channel or role, or those of
+    /// one of its subscriptions, queues codes that failed to be delivered
+    /// again.
+    async fn set_guild_state(&self, guild: GuildId, enabled: bool) -> Result<()>;
+
+    async fn set_guild_alert_role(&self, guild: GuildId, role: Option<RoleId>) -> Result<()>;
 
-    pub async fn set_guild_alert_channel(
+    async fn set_guild_manager_role(&self, guild: GuildId, role: Option<RoleId>) -> Result<()>;
+
+    async fn set_guild_alert_channel(
         &self,
         guild: GuildId,
         channel: Option<ChannelId>,
-    ) -> Result<()> {
-        let res = self
-            .client
-            .execute(
-                "UPDATE guilds SET alert_channel = ?1 WHERE guild_id = ?2",
-                params![channel.map(|id| i64::from(id)), guild.to_string()],
-            )
-            .await?;
-        if res != 1 {
-            return Err(anyhow!("Update did not succeed. Affected rows: {}", res));
+    ) -> Result<()>;
+
+    /// Subscriptions stored for the guild, ordered by game.
+    async fn subscriptions(&self, guild: GuildId) -> Result<Vec<TursoSubscription>>;
+
+    async fn set_subscription_state(&self, guild: GuildId, game: Game, enabled: bool)
+        -> Result<()>;
+
+    async fn set_subscription_channel(
+        &self,
+        guild: GuildId,
+        game: Game,
+        channel: Option<ChannelId>,
+    ) -> Result<()>;
+
+    async fn set_subscription_role(
+        &self,
+        guild: GuildId,
+        game: Game,
+        role: Option<RoleId>,
+    ) -> Result<()>;
+
+    /// Unsets the deleted channel wherever the guild uses it. Returns whether
+    /// any setting used it.
+    async fn forget_channel(&self, guild: GuildId, channel: ChannelId) -> Result<bool>;
+
+    /// Unsets the deleted role wherever the guild uses it, including the
+    /// manager role. Returns whether any setting used it.
+    async fn forget_role(&self, guild: GuildId, role: RoleId) -> Result<bool>;
+
+    /// Stores the codes currently listed for `game`. Known codes not listed
+    /// anymore are invalidated, unless `codes` is empty: a source listing
+    /// nothing is treated as unavailable.
+    async fn record_codes(&self, game: Game, codes: &[ScrapedCode]) -> Result<()>;
+
+    /// Invalidates every known code of `game`, once its sources confirmed
+    /// that they list no codes.
+    async fn expire_codes(&self, game: Game) -> Result<()>;
+
+    /// Codes currently listed by any source, ordered by game.
+    async fn valid_codes(&self) -> Result<Vec<TursoCode>>;
+
+    /// Valid codes every enabled subscription of every enabled guild has not
+    /// received yet. One update per guild and game, none for those without
+    /// pending codes. Subscriptions without an alert channel keep their codes
+    /// pending until a channel is set.
+    async fn pending_updates(&self) -> Result<Vec<GuildUpdate>>;
+
+    /// Queues the codes of `update` for sending. Queued codes are not pending
+    /// anymore.
+    async fn enqueue(&self, update: &GuildUpdate) -> Result<()>;
+
+    /// Queued alerts due at `now`, oldest first.
+    async fn due_outbox(&self, now: DateTime<Utc>) -> Result<Vec<OutboxEntry>>;
+
+    /// Marks the codes of the entry as sent in `message` and removes it from
+    /// the queue. `message` is `None` if nothing had to be sent.
+    async fn complete_outbox(&self, id: i64, message: Option<MessageId>) -> Result<()>;
+
+    /// Schedules another attempt of the entry.
+    async fn retry_outbox(
+        &self,
+        id: i64,
+        next_attempt_at: DateTime<Utc>,
+        error: &str,
+    ) -> Result<()>;
+
+    /// Gives up on the entry. Its codes are marked as failed and are not
+    /// queued again.
+    async fn dead_letter_outbox(&self, id: i64, error: &str) -> Result<()>;
+
+    async fn delivery_stats(&self, guild: GuildId) -> Result<DeliveryStats>;
+
+    /// Subscribes the user to DM alerts of `game`, sent to `dm_channel`.
+    /// Returns whether the user was not subscribed already.
+    async fn subscribe_user(&self, user: UserId, game: Game, dm_channel: ChannelId)
+        -> Result<bool>;
+
+    /// Removes the DM subscription to `game`, or all of them if `game` is
+    /// `None`. Once the user has no subscriptions left their deliveries and
+    /// queued alerts are deleted as well. Returns whether there was anything
+    /// to remove.
+    async fn unsubscribe_user(&self, user: UserId, game: Option<Game>) -> Result<bool>;
+
+    /// Sent alerts listing codes that expired, or were listed again, since the
+    /// alert was last updated.
+    async fn stale_alerts(&self) -> Result<Vec<SentAlert>>;
+
+    /// Records that `message` shows the current validity of its codes.
+    async fn mark_alert_updated(&self, message: MessageId) -> Result<()>;
+
+    /// All subscriptions of a guild, including the implicit one to the default
+    /// game.
+    async fn guild_subscriptions(&self, guild: GuildId) -> Result<Vec<TursoSubscription>> {
+        let mut subscriptions = self.subscriptions(guild).await?;
+        if !subscriptions.iter().any(|sub| sub.game == Game::DEFAULT) {
+            subscriptions.insert(0, TursoSubscription::implicit(guild));
         }
-        Ok(())
+        Ok(subscriptions)
+    }
+
+    async fn guild_alert_role(&self, guild: GuildId) -> Result<Option<RoleId>> {
+        Ok(self.guild(guild).await?.and_then(|g| g.alert_role))
     }
 
-    pub async fn update_guilds(&self, guilds: &Vec<UnavailableGuild>) -> Result<()> {
+    /// The role pinged for alerts of `game`, falling back to the role of the
+    /// guild.
+    async fn game_alert_role(&self, guild: GuildId, game: Game) -> Result<Option<RoleId>> {
+        let Some(settings) = self.guild(guild).await? else {
+            return Ok(None);
+        };
+        Ok(self
+            .guild_subscriptions(guild)
+            .await?
+            .iter()
+            .find(|subscription| subscription.game == game)
+            .map_or(settings.alert_role, |subscription| {
+                subscription.role(&settings)
+            }))
+    }
+
+    async fn guild_alert_channel(&self, guild: GuildId) -> Result<Option<ChannelId>> {
+        Ok(self.guild(guild).await?.and_then(|g| g.alert_channel))
+    }
+
+    async fn update_guilds(&self, guilds: &Vec<UnavailableGuild>) -> Result<()> {
         for guild in guilds {
             let _ = self.try_add_guild(guild.id).await?;
         }
 
         Ok(())
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use libsql::Database;
+    use serenity::all::{ChannelId, GuildId, MessageId, RoleId, UserId};
+
+    use chrono::{Duration, Utc};
+    use futures::future::join_all;
+
+    use super::{migrations, GuildUpdate, MemoryStore, Recipient, Store, TursoCode, TursoDb};
+    use crate::games::Game;
+    use crate::scraper::ScrapedCode;
+
+    async fn turso() -> TursoDb {
+        let client = Database::open(":memory:").unwrap().connect().unwrap();
+        migrations::run(&client).await.unwrap();
+        let db = TursoDb::new(Arc::new(client)).await.unwrap();
+        db.seed_games().await.unwrap();
+        db
+    }
 
-    pub async fn try_add_guild(&self, guild: GuildId) -> Result<bool> {
-        let guilds = self.guilds().await?;
-        if guilds.iter().find(|info| info.guild_id == guild).is_none() {
-            warn!(guild=?&guild, "New guild joined. Adding to config");
-            let res = self
-                .client
-                .execute(
-                    "INSERT INTO guilds (id, guild_id) VALUES (NULL, ?1);",
-                    [guild.to_string()],
-                )
-                .await?;
-            if res != 1 {
-                return Err(anyhow!("Insert did not succeed. Affected rows: {}", res));
+    fn scraped(code: &str) -> ScrapedCode {
+        ScrapedCode {
+            code: code.to_string(),
+            rewards: vec![],
+            expires_at: None,
+            is_new: false,
+            source: Some("test".to_string()),
+        }
+    }
+
+    /// Pending codes by guild and game.
+    async fn pending(store: &dyn Store) -> BTreeMap<(u64, Game), Vec<String>> {
+        store
+            .pending_updates()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|update| {
+                let Recipient::Guild(guild) = update.recipient else {
+                    return None;
+                };
+                let codes = update
+                    .codes
+                    .unwrap()
+                    .into_iter()
+                    .map(|code| code.code)
+                    .collect();
+                Some(((guild.get(), update.game), codes))
+            })
+            .collect()
+    }
+
+    /// Adds a guild with an alert channel, so it has pending codes.
+    async fn add_guild(store: &dyn Store, id: u64) {
+        let guild = GuildId::new(id);
+        store.try_add_guild(guild).await.unwrap();
+        store
+            .set_guild_alert_channel(guild, Some(ChannelId::new(id * 10)))
+            .await
+            .unwrap();
+    }
+
+    /// Codes currently listed by any source.
+    async fn valid(store: &dyn Store) -> Vec<String> {
+        store
+            .valid_codes()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|code| code.code)
+            .collect()
+    }
+
+    /// Sends `codes` to the guild through the outbox.
+    async fn deliver(store: &dyn Store, guild: u64, codes: Vec<TursoCode>) {
+        let update = GuildUpdate {
+            recipient: Recipient::Guild(GuildId::new(guild)),
+            game: codes[0].game,
+            role: None,
+            chan: None,
+            codes: Some(codes),
+            enabled: true,
+        };
+        store.enqueue(&update).await.unwrap();
+        for entry in store.due_outbox(Utc::now()).await.unwrap() {
+            if entry.update.recipient == update.recipient {
+                store.complete_outbox(entry.id, None).await.unwrap();
             }
-            return Ok(true);
         }
+    }
 
-        Ok(false)
+    async fn codes_are_delivered_once(store: &dyn Store) {
+        let guild = GuildId::new(1);
+        store.try_add_guild(guild).await.unwrap();
+        store
+            .set_guild_alert_channel(guild, Some(ChannelId::new(10)))
+            .await
+            .unwrap();
+        store
+            .record_codes(Game::StarRail, &[scraped("A"), scraped("B")])
+            .await
+            .unwrap();
+
+        let updates = store.pending_updates().await.unwrap();
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].chan, Some(ChannelId::new(10)));
+        assert_eq!(updates[0].codes.as_ref().unwrap().len(), 2);
+
+        store.enqueue(&updates[0]).await.unwrap();
+        assert!(store.pending_updates().await.unwrap().is_empty());
+
+        let due = store.due_outbox(Utc::now()).await.unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].update.chan, Some(ChannelId::new(10)));
+        assert_eq!(due[0].update.codes.as_ref().unwrap().len(), 2);
+
+        assert_eq!(store.delivery_stats(guild).await.unwrap().queued, 2);
+
+        store
+            .complete_outbox(due[0].id, Some(MessageId::new(100)))
+            .await
+            .unwrap();
+        assert!(store.due_outbox(Utc::now()).await.unwrap().is_empty());
+        assert!(store.pending_updates().await.unwrap().is_empty());
+
+        let stats = store.delivery_stats(guild).await.unwrap();
+        assert_eq!(stats.queued, 0);
+        let (last, sent_at) = stats.last_sent.unwrap();
+        assert_eq!(last.code, "B");
+        assert!(sent_at.is_some());
     }
 
-    async fn validate_guild(
-        &self,
-        guild: &TursoGuild,
-        ctx: &Context,
-    ) -> Result<Option<InvalidInfo>> {
-        if guild.enabled == 0 {
-            return Ok(None);
+    async fn unlisted_codes_are_invalidated(store: &dyn Store) {
+        add_guild(store, 1).await;
+        store
+            .record_codes(Game::StarRail, &[scraped("A"), scraped("B")])
+            .await
+            .unwrap();
+        store
+            .record_codes(Game::Genshin, &[scraped("G")])
+            .await
+            .unwrap();
+        store
+            .record_codes(Game::StarRail, &[scraped("B")])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            pending(store).await,
+            BTreeMap::from([((1, Game::StarRail), vec!["B".to_string()])])
+        );
+        // Other games are not affected
+        assert_eq!(valid(store).await, vec!["B", "G"]);
+    }
+
+    async fn empty_scrape_keeps_codes(store: &dyn Store) {
+        store
+            .record_codes(Game::StarRail, &[scraped("A")])
+            .await
+            .unwrap();
+        // Listing nothing does not expire anything
+        store.record_codes(Game::StarRail, &[]).await.unwrap();
+
+        assert_eq!(valid(store).await, vec!["A"]);
+    }
+
+    async fn listing_nothing_expires_codes(store: &dyn Store) {
+        store
+            .record_codes(Game::StarRail, &[scraped("A")])
+            .await
+            .unwrap();
+        store
+            .record_codes(Game::Genshin, &[scraped("G")])
+            .await
+            .unwrap();
+        store.expire_codes(Game::StarRail).await.unwrap();
+        assert_eq!(valid(store).await, vec!["G"]);
+
+        // Listed again later
+        store
+            .record_codes(Game::StarRail, &[scraped("A")])
+            .await
+            .unwrap();
+        assert_eq!(valid(store).await, vec!["A", "G"]);
+    }
+
+    async fn quotes_in_codes_are_not_sql(store: &dyn Store) {
+        let hostile = "X') OR 1=1; DROP TABLE codes; --";
+        store
+            .record_codes(Game::StarRail, &[scraped("A"), scraped(hostile)])
+            .await
+            .unwrap();
+        store
+            .record_codes(Game::StarRail, &[scraped("A"), scraped(hostile)])
+            .await
+            .unwrap();
+
+        assert_eq!(valid(store).await, vec!["A", hostile]);
+    }
+
+    async fn first_source_is_kept(store: &dyn Store) {
+        store
+            .record_codes(Game::StarRail, &[scraped("A")])
+            .await
+            .unwrap();
+        let mut relisted = scraped("A");
+        relisted.source = Some("other".to_string());
+        store
+            .record_codes(Game::StarRail, &[relisted])
+            .await
+            .unwrap();
+
+        let code = store.valid_codes().await.unwrap().remove(0);
+        assert_eq!(code.source.as_deref(), Some("test"));
+    }
+
+    async fn every_guild_gets_its_missing_codes(store: &dyn Store) {
+        for id in 1..=30 {
+            add_guild(store, id).await;
         }
-        if let Ok(g) = Self::get_guild(&guild.guild_id, &ctx).await {
-            let channel_valid = guild.alert_channel.is_some()
-                && g.channels(&ctx.http)
-                    .await?
-                    .iter()
-                    .find(|(id, _)| **id == guild.alert_channel.unwrap())
-                    .is_some();
-            let mut role_valid = true;
-            if guild.alert_role.is_some() {
-                role_valid = g
-                    .roles
-                    .iter()
-                    .find(|(id, _)| **id == guild.alert_role.unwrap())
-                    .is_some();
-            }
-            if !channel_valid && !role_valid {
-                Ok(Some(InvalidInfo::Both(
-                    guild.alert_channel,
-                    guild.alert_role.unwrap(),
-                )))
-            } else if !channel_valid {
-                Ok(Some(InvalidInfo::Channel(guild.alert_channel)))
-            } else if !role_valid {
-                Ok(Some(InvalidInfo::Role(guild.alert_role.unwrap())))
+        store
+            .record_codes(Game::StarRail, &[scraped("A"), scraped("B")])
+            .await
+            .unwrap();
+        store
+            .record_codes(Game::Genshin, &[scraped("G")])
+            .await
+            .unwrap();
+
+        // 1 already has everything
+        let all = store.pending_updates().await.unwrap();
+        let codes_of = |id: u64, code: &str| {
+            all.iter()
+                .filter(|update| update.recipient == Recipient::Guild(GuildId::new(id)))
+                .flat_map(|update| update.codes.clone().unwrap())
+                .filter(|c| c.code == code)
+                .collect::<Vec<_>>()
+        };
+        deliver(store, 1, [codes_of(1, "A"), codes_of(1, "B")].concat()).await;
+        // 2 is disabled
+        store.set_guild_state(GuildId::new(2), false).await.unwrap();
+        // 3 only wants Genshin
+        store
+            .set_subscription_state(GuildId::new(3), Game::StarRail, false)
+            .await
+            .unwrap();
+        store
+            .set_subscription_state(GuildId::new(3), Game::Genshin, true)
+            .await
+            .unwrap();
+        // 4 wants both and only got B
+        store
+            .set_subscription_state(GuildId::new(4), Game::Genshin, true)
+            .await
+            .unwrap();
+        deliver(store, 4, codes_of(4, "B")).await;
+        // 5 has a disabled Genshin subscription
+        store
+            .set_subscription_state(GuildId::new(5), Game::Genshin, false)
+            .await
+            .unwrap();
+
+        let hsr = vec!["A".to_string(), "B".to_string()];
+        let mut expected = BTreeMap::new();
+        expected.insert((3, Game::Genshin), vec!["G".to_string()]);
+        expected.insert((4, Game::StarRail), vec!["A".to_string()]);
+        expected.insert((4, Game::Genshin), vec!["G".to_string()]);
+        expected.insert((5, Game::StarRail), hsr.clone());
+        for id in 6..=30 {
+            expected.insert((id, Game::StarRail), hsr.clone());
+        }
+        assert_eq!(pending(store).await, expected);
+    }
+
+    async fn codes_are_tracked_per_game(store: &dyn Store) {
+        add_guild(store, 1).await;
+        store
+            .set_subscription_state(GuildId::new(1), Game::Genshin, true)
+            .await
+            .unwrap();
+        store
+            .record_codes(Game::StarRail, &[scraped("SHARED")])
+            .await
+            .unwrap();
+        store
+            .record_codes(Game::Genshin, &[scraped("SHARED")])
+            .await
+            .unwrap();
+        assert_eq!(
+            pending(store).await,
+            BTreeMap::from([
+                ((1, Game::StarRail), vec!["SHARED".to_string()]),
+                ((1, Game::Genshin), vec!["SHARED".to_string()]),
+            ])
+        );
+
+        // Expiring in one game keeps the code of the other
+        store
+            .record_codes(Game::StarRail, &[scraped("OTHER")])
+            .await
+            .unwrap();
+        let valid: Vec<_> = store
+            .valid_codes()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|code| (code.game, code.code))
+            .collect();
+        assert_eq!(
+            valid,
+            vec![
+                (Game::StarRail, "OTHER".to_string()),
+                (Game::Genshin, "SHARED".to_string()),
+            ]
+        );
+    }
+
+    async fn codes_wait_for_an_alert_channel(store: &dyn Store) {
+        let guild = GuildId::new(1);
+        store.try_add_guild(guild).await.unwrap();
+        store
+            .record_codes(Game::StarRail, &[scraped("A")])
+            .await
+            .unwrap();
+        assert!(store.pending_updates().await.unwrap().is_empty());
+
+        store
+            .set_guild_alert_channel(guild, Some(ChannelId::new(10)))
+            .await
+            .unwrap();
+        assert_eq!(
+            pending(store).await,
+            BTreeMap::from([((1, Game::StarRail), vec!["A".to_string()])])
+        );
+    }
+
+    async fn outbox_retries_and_dead_letters(store: &dyn Store) {
+        let guild = GuildId::new(1);
+        add_guild(store, 1).await;
+        store
+            .record_codes(Game::StarRail, &[scraped("A")])
+            .await
+            .unwrap();
+        let update = store.pending_updates().await.unwrap().remove(0);
+        store.enqueue(&update).await.unwrap();
+        let id = store.due_outbox(Utc::now()).await.unwrap()[0].id;
+
+        let later = Utc::now() + Duration::minutes(5);
+        store.retry_outbox(id, later, "502").await.unwrap();
+        assert!(store.due_outbox(Utc::now()).await.unwrap().is_empty());
+        let due = store.due_outbox(later).await.unwrap();
+        assert_eq!(due[0].attempts, 1);
+        assert_eq!(due[0].update.codes.as_ref().unwrap()[0].code, "A");
+
+        store.dead_letter_outbox(id, "403").await.unwrap();
+        assert!(store.due_outbox(later).await.unwrap().is_empty());
+        let stats = store.delivery_stats(guild).await.unwrap();
+        assert_eq!(stats.failed, 1);
+        assert!(stats.last_sent.is_none());
+        // Given up codes are not queued again until the settings change
+        assert!(store.pending_updates().await.unwrap().is_empty());
+        store
+            .set_guild_alert_channel(guild, Some(ChannelId::new(11)))
+            .await
+            .unwrap();
+        assert_eq!(
+            pending(store).await,
+            BTreeMap::from([((1, Game::StarRail), vec!["A".to_string()])])
+        );
+    }
+
+    async fn concurrent_outbox_updates_are_kept(store: &dyn Store) {
+        for guild in 1..=16 {
+            add_guild(store, guild).await;
+        }
+        store
+            .record_codes(Game::StarRail, &[scraped("A")])
+            .await
+            .unwrap();
+        for update in store.pending_updates().await.unwrap() {
+            store.enqueue(&update).await.unwrap();
+        }
+        let entries = store.due_outbox(Utc::now()).await.unwrap();
+        assert_eq!(entries.len(), 16);
+
+        // Sent and failed alerts are handled concurrently by the outbox worker
+        let later = Utc::now() + Duration::hours(1);
+        join_all(entries.iter().map(|entry| async move {
+            if entry.id % 2 == 0 {
+                store
+                    .complete_outbox(entry.id, Some(MessageId::new(entry.id as u64)))
+                    .await
             } else {
-                Ok(None)
-            }
-        } else {
-            self.client
-                .execute(
-                    "DELETE FROM guilds WHERE guild_id = ?1;",
-                    [guild.guild_id.to_string()],
-                )
-                .await?;
-            warn!(
-                id = ?guild.guild_id,
-                "Could not get guild. Removing it from known guilds."
-            );
-            Ok(None)
-        }
-    }
-
-    pub async fn validate_info(&self, ctx: &Context) -> Result<Vec<(GuildId, InvalidInfo)>> {
-        let mut invalid_guilds: Vec<(GuildId, InvalidInfo)> = vec![];
-        for guild in self.guilds().await?.iter() {
-            if let Some(info) = self.validate_guild(guild, ctx).await? {
-                invalid_guilds.push((guild.guild_id, info));
+                store.retry_outbox(entry.id, later, "flaky").await
             }
+        }))
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<Vec<_>>>()
+        .unwrap();
+
+        let retried = store.due_outbox(later).await.unwrap();
+        assert_eq!(retried.len(), 8);
+        assert!(retried
+            .iter()
+            .all(|entry| entry.id % 2 == 1 && entry.attempts == 1));
+        for entry in entries {
+            let Recipient::Guild(guild) = entry.update.recipient else {
+                panic!("Only guilds are subscribed");
+            };
+            let stats = store.delivery_stats(guild).await.unwrap();
+            assert_eq!(stats.queued, entry.id % 2);
+            assert_eq!(stats.last_sent.is_some(), entry.id % 2 == 0);
         }
+    }
 
-        Ok(invalid_guilds)
+    async fn expired_codes_update_their_alert(store: &dyn Store) {
+        let guild = GuildId::new(1);
+        store.try_add_guild(guild).await.unwrap();
+        store
+            .set_guild_alert_channel(guild, Some(ChannelId::new(10)))
+            .await
+            .unwrap();
+        store
+            .record_codes(Game::StarRail, &[scraped("A"), scraped("B")])
+            .await
+            .unwrap();
+        let update = store.pending_updates().await.unwrap().remove(0);
+        store.enqueue(&update).await.unwrap();
+        let id = store.due_outbox(Utc::now()).await.unwrap()[0].id;
+        store
+            .complete_outbox(id, Some(MessageId::new(100)))
+            .await
+            .unwrap();
+        assert!(store.stale_alerts().await.unwrap().is_empty());
+
+        store
+            .record_codes(Game::StarRail, &[scraped("B")])
+            .await
+            .unwrap();
+        let stale = store.stale_alerts().await.unwrap();
+        assert_eq!(stale.len(), 1);
+        assert_eq!(stale[0].channel, ChannelId::new(10));
+        assert_eq!(stale[0].message, MessageId::new(100));
+        let validity: Vec<_> = stale[0]
+            .codes
+            .iter()
+            .map(|code| (code.code.as_str(), code.is_valid()))
+            .collect();
+        assert_eq!(validity, vec![("A", false), ("B", true)]);
+
+        store.mark_alert_updated(MessageId::new(100)).await.unwrap();
+        assert!(store.stale_alerts().await.unwrap().is_empty());
+
+        // Listed again
+        store
+            .record_codes(Game::StarRail, &[scraped("A"), scraped("B")])
+            .await
+            .unwrap();
+        assert_eq!(store.stale_alerts().await.unwrap().len(), 1);
     }
 
-    pub async fn alert_guild_invalid_info(
-        &self,
-        ctx: &Context,
-        reason: &(GuildId, InvalidInfo),
-    ) -> Result<()> {
-        let default_chan = self.get_default_channel(reason.0, &ctx).await?;
-        warn!(guild=?&reason.0, invalid=?&reason.1, "Guild has invalid info");
-        match reason.1 {
-            InvalidInfo::Channel(chan_id) => {
-                Self::alert_invalid_channel(&ctx, chan_id, &default_chan).await?;
-            }
-            InvalidInfo::Role(role_id) => {
-                Self::alert_invalid_role(&ctx, role_id, &default_chan).await?;
-            }
-            InvalidInfo::Both(chan_id, role_id) => {
-                Self::alert_invalid_channel(&ctx, chan_id, &default_chan).await?;
-                Self::alert_invalid_role(&ctx, role_id, &default_chan).await?;
-            }
+    async fn users_get_codes_by_dm(store: &dyn Store) {
+        let user = UserId::new(7);
+        let dm = ChannelId::new(70);
+        // Guilds and users do not share deliveries
+        store.try_add_guild(GuildId::new(1)).await.unwrap();
+        assert!(store.subscribe_user(user, Game::Genshin, dm).await.unwrap());
+        assert!(!store.subscribe_user(user, Game::Genshin, dm).await.unwrap());
+        store
+            .record_codes(Game::Genshin, &[scraped("G")])
+            .await
+            .unwrap();
+        store
+            .record_codes(Game::StarRail, &[scraped("A")])
+            .await
+            .unwrap();
+
+        let updates = store.pending_updates().await.unwrap();
+        let dms: Vec<_> = updates
+            .iter()
+            .filter(|update| update.recipient == Recipient::User(user))
+            .collect();
+        assert_eq!(dms.len(), 1);
+        assert_eq!(dms[0].game, Game::Genshin);
+        assert_eq!(dms[0].chan, Some(dm));
+        assert_eq!(dms[0].role, None);
+
+        for update in updates.iter() {
+            store.enqueue(update).await.unwrap();
         }
-        tracing::info!(
-            "Sent alert matching {:#?} to guild {}",
-            (*reason).1,
-            (*reason).0
+        for entry in store.due_outbox(Utc::now()).await.unwrap() {
+            store
+                .complete_outbox(entry.id, Some(MessageId::new(entry.id as u64)))
+                .await
+                .unwrap();
+        }
+        assert!(store.pending_updates().await.unwrap().is_empty());
+
+        assert!(store.unsubscribe_user(user, None).await.unwrap());
+        assert!(!store
+            .unsubscribe_user(user, Some(Game::Genshin))
+            .await
+            .unwrap());
+        store
+            .record_codes(Game::Genshin, &[scraped("G"), scraped("H")])
+            .await
+            .unwrap();
+        assert!(store
+            .pending_updates()
+            .await
+            .unwrap()
+            .iter()
+            .all(|update| update.recipient != Recipient::User(user)));
+
+        // Nothing is remembered about the user, they start over like anyone new
+        assert!(store.subscribe_user(user, Game::Genshin, dm).await.unwrap());
+        let updates = store.pending_updates().await.unwrap();
+        let dm = updates
+            .iter()
+            .find(|update| update.recipient == Recipient::User(user))
+            .unwrap();
+        assert_eq!(dm.codes.as_ref().unwrap().len(), 2);
+    }
+
+    async fn removed_guilds_are_kept_until_purged(store: &dyn Store) {
+        let guild = GuildId::new(1);
+        let role = RoleId::new(20);
+        add_guild(store, 1).await;
+        store.set_guild_alert_role(guild, Some(role)).await.unwrap();
+        store
+            .record_codes(Game::StarRail, &[scraped("A")])
+            .await
+            .unwrap();
+        deliver(store, 1, store.valid_codes().await.unwrap()).await;
+
+        let removed_at = Utc::now() - Duration::days(10);
+        store.remove_guild(guild, removed_at).await.unwrap();
+        assert!(store.guilds().await.unwrap().is_empty());
+        assert!(store.guild(guild).await.unwrap().is_none());
+        store
+            .record_codes(Game::StarRail, &[scraped("A"), scraped("B")])
+            .await
+            .unwrap();
+        assert!(pending(store).await.is_empty());
+
+        // Re-inviting restores the settings and what was delivered
+        assert!(store.try_add_guild(guild).await.unwrap());
+        assert!(!store.try_add_guild(guild).await.unwrap());
+        assert_eq!(store.guild_alert_role(guild).await.unwrap(), Some(role));
+        assert_eq!(
+            pending(store).await.get(&(1, Game::StarRail)),
+            Some(&vec!["B".to_string()])
+        );
+
+        store.remove_guild(guild, removed_at).await.unwrap();
+        assert!(store
+            .purge_removed_guilds(removed_at - Duration::days(1))
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            store.purge_removed_guilds(Utc::now()).await.unwrap(),
+            vec![guild]
+        );
+
+        // Nothing is left after the purge
+        assert!(store.try_add_guild(guild).await.unwrap());
+        assert_eq!(store.guild_alert_role(guild).await.unwrap(), None);
+        assert!(pending(store).await.is_empty());
+        add_guild(store, 1).await;
+        assert_eq!(
+            pending(store).await.get(&(1, Game::StarRail)),
+            Some(&vec!["A".to_string(), "B".to_string()])
         );
-        Ok(())
     }
 
-    async fn alert_invalid_channel(
-        ctx: &Context,
-        chan_id: Option<ChannelId>,
-        default_chan: &GuildChannel,
-    ) -> Result<()> {
-        if let Some(id) = chan_id {
-            default_chan.send_message(&ctx.http, CreateMessage::new().content(format!("The channel (id={}) you set for the alerts is not valid anymore. Please set it again. The guild will be disabled. Re-enable the guild using /enable", id))).await?;
-        } else {
-            default_chan.send_message(&ctx.http, CreateMessage::new().content("No alert channel found. You might want to set the channel using: `/alert-channel`. The guild will be disabled. Re-enable the guild using /enable")).await?;
+    async fn manager_role_is_stored(store: &dyn Store) {
+        let guild = GuildId::new(1);
+        store.try_add_guild(guild).await.unwrap();
+        store
+            .set_guild_manager_role(guild, Some(RoleId::new(5)))
+            .await
+            .unwrap();
+        let stored = store.guild(guild).await.unwrap().unwrap();
+        assert_eq!(stored.manager_role, Some(RoleId::new(5)));
+
+        store.set_guild_manager_role(guild, None).await.unwrap();
+        let stored = store.guild(guild).await.unwrap().unwrap();
+        assert_eq!(stored.manager_role, None);
+    }
+
+    async fn deleted_channels_and_roles_are_forgotten(store: &dyn Store) {
+        let guild = GuildId::new(1);
+        let other = GuildId::new(2);
+        let channel = ChannelId::new(10);
+        let role = RoleId::new(20);
+        for id in [guild, other] {
+            store.try_add_guild(id).await.unwrap();
+            store
+                .set_guild_alert_channel(id, Some(channel))
+                .await
+                .unwrap();
+            store.set_guild_alert_role(id, Some(role)).await.unwrap();
         }
-        Ok(())
+        store
+            .set_guild_manager_role(guild, Some(role))
+            .await
+            .unwrap();
+        store
+            .set_subscription_channel(guild, Game::Genshin, Some(channel))
+            .await
+            .unwrap();
+        store
+            .set_subscription_role(guild, Game::Genshin, Some(role))
+            .await
+            .unwrap();
+
+        assert!(store.forget_channel(guild, channel).await.unwrap());
+        assert!(!store.forget_channel(guild, channel).await.unwrap());
+        assert!(store.forget_role(guild, role).await.unwrap());
+        assert!(!store.forget_role(guild, role).await.unwrap());
+
+        let stored = store.guild(guild).await.unwrap().unwrap();
+        assert_eq!(stored.alert_channel, None);
+        assert_eq!(stored.alert_role, None);
+        assert_eq!(stored.manager_role, None);
+        let subscription = store.guild_subscriptions(guild).await.unwrap();
+        let genshin = subscription
+            .iter()
+            .find(|sub| sub.game == Game::Genshin)
+            .unwrap();
+        assert_eq!(genshin.alert_channel, None);
+        assert_eq!(genshin.alert_role, None);
+
+        // Other guilds keep their settings
+        let stored = store.guild(other).await.unwrap().unwrap();
+        assert_eq!(stored.alert_channel, Some(channel));
+        assert_eq!(stored.alert_role, Some(role));
     }
 
-    async fn alert_invalid_role(
-        ctx: &Context,
-        role_id: RoleId,
-        default_chan: &GuildChannel,
-    ) -> Result<()> {
-        default_chan.send_message(&ctx.http, CreateMessage::new().content(format!("The role (id={}) you set for the alerts is not valid anymore. Please set it again. The guild will be disabled. Re-enable the guild using /enable", role_id))).await?;
-        Ok(())
+    async fn subscriptions_fall_back_to_guild_settings(store: &dyn Store) {
+        let guild = GuildId::new(1);
+        store.try_add_guild(guild).await.unwrap();
+        store
+            .set_guild_alert_channel(guild, Some(ChannelId::new(10)))
+            .await
+            .unwrap();
+        store
+            .set_subscription_channel(guild, Game::Genshin, Some(ChannelId::new(20)))
+            .await
+            .unwrap();
+        store
+            .record_codes(Game::StarRail, &[scraped("HSR")])
+            .await
+            .unwrap();
+        store
+            .record_codes(Game::Genshin, &[scraped("GENSHIN")])
+            .await
+            .unwrap();
+
+        let updates = store.pending_updates().await.unwrap();
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[0].game, Game::StarRail);
+        assert_eq!(updates[0].chan, Some(ChannelId::new(10)));
+        assert_eq!(updates[1].game, Game::Genshin);
+        assert_eq!(updates[1].chan, Some(ChannelId::new(20)));
     }
 
-    async fn get_default_channel(&self, guild_id: GuildId, ctx: &Context) -> Result<GuildChannel> {
-        return if let Ok(guild) = Self::get_guild(&guild_id, &ctx).await {
-            return if let Ok(channels) = guild.channels(&ctx.http).await {
-                let default_chan: GuildChannel;
-                if let Some(system_channel_id) = guild.system_channel_id {
-                    default_chan = channels
-                        .iter()
-                        .find(|(id, _)| **id == system_channel_id)
-                        .expect("Should exist")
-                        .1
-                        .clone();
-                } else {
-                    if let Some((_, first_channel)) = channels.iter().find(|_| true) {
-                        default_chan = first_channel.clone();
-                    } else {
-                        return Err(anyhow!("Could not get any channel for guild: {}", guild_id));
+    macro_rules! store_tests {
+        ($($name:ident),*) => {
+            mod memory {
+                $(
+                    #[tokio::test]
+                    async fn $name() {
+                        super::$name(&super::MemoryStore::new()).await;
                     }
-                }
-                return Ok(default_chan);
-            } else {
-                Err(anyhow!("Could not get channels for guild: {}", guild_id))
-            };
-        } else {
-            Err(anyhow!("Could not retrieve info for guild {}", guild_id))
-        };
-    }
+                )*
+            }
 
-    pub async fn get_guild(id: &GuildId, ctx: &Context) -> Result<PartialGuild> {
-        Ok(Guild::get(&ctx.http, id).await?)
+            mod turso {
+                $(
+                    #[tokio::test]
+                    async fn $name() {
+                        super::$name(&super::turso().await).await;
+                    }
+                )*
+            }
+        };
     }
-}
 
-#[derive(Debug)]
-pub enum InvalidInfo {
-    Channel(Option<ChannelId>),
-    Role(RoleId),
-    Both(Option<ChannelId>, RoleId),
+    store_tests!(
+        codes_are_delivered_once,
+        unlisted_codes_are_invalidated,
+        empty_scrape_keeps_codes,
+        listing_nothing_expires_codes,
+        quotes_in_codes_are_not_sql,
+        first_source_is_kept,
+        every_guild_gets_its_missing_codes,
+        codes_are_tracked_per_game,
+        codes_wait_for_an_alert_channel,
+        outbox_retries_and_dead_letters,
+        concurrent_outbox_updates_are_kept,
+        expired_codes_update_their_alert,
+        manager_role_is_stored,
+        users_get_codes_by_dm,
+        removed_guilds_are_kept_until_purged,
+        deleted_channels_and_roles_are_forgotten,
+        subscriptions_fall_back_to_guild_settings
+    );
 }
@@ -0,0 +1,830 @@
+use std::future::Future;
+use std::{i64, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use libsql::params::Params;
+use libsql::{params, Connection, Row, Value, ValueType};
+use serenity::all::{ChannelId, GuildId, MessageId, RoleId, UserId};
+use serenity::async_trait;
+use tokio::sync::{Mutex, MutexGuard};
+
+use super::{
+    parse_timestamp, DeliveryStats, DeliveryStatus, GuildUpdate, OutboxEntry, Recipient, SentAlert,
+    Store, TursoCode, TursoGuild, TursoSubscription,
+};
+use crate::games::Game;
+use crate::scraper::ScrapedCode;
+
+pub struct TursoDb {
+    /// Shared by all tasks. Every statement runs while it is locked, so none
+    /// of them ends up in the open transaction of another task.
+    client: Mutex<Arc<Connection>>,
+}
+
+impl TursoDb {
+    pub async fn new(client: Arc<Connection>) -> Result<Self> {
+        Ok(Self {
+            client: Mutex::new(client),
+        })
+    }
+
+    /// Locks the connection for the statements of one [`Store`] call.
+    async fn connection(&self) -> Result<MutexGuard<'_, Arc<Connection>>> {
+        let client = self.client.lock().await;
+        if !client.is_autocommit() {
+            // Left open by a transaction whose future was dropped
+            warn!("Rolling back an abandoned transaction");
+            client.execute_batch("ROLLBACK;").await?;
+        }
+        Ok(client)
+    }
+
+    /// Runs `body` in a transaction, rolling it back if `body` fails.
+    ///
+    /// [`libsql::Transaction`] is not `Send` and cannot be held across the
+    /// awaits of a [`Store`] method, so the transaction is managed with plain
+    /// statements.
+    async fn transaction<T, F, Fut>(&self, body: F) -> Result<T>
+    where
+        F: FnOnce(Arc<Connection>) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let client = self.connection().await?;
+        client.execute_batch("BEGIN;").await?;
+        match body(client.clone()).await {
+            Ok(value) => {
+                client.execute_batch("COMMIT;").await?;
+                Ok(value)
+            }
+            Err(err) => {
+                if let Err(rollback) = client.execute_batch("ROLLBACK;").await {
+                    error!(
+                        reason = rollback.to_string(),
+                        "Could not roll back transaction"
+                    );
+                }
+                Err(err)
+            }
+        }
+    }
+
+    pub async fn seed_games(&self) -> Result<()> {
+        let client = self.connection().await?;
+        for game in Game::ALL {
+            client
+                .execute(
+                    "INSERT INTO games (id, name, slug) VALUES (?1, ?2, ?3) ON CONFLICT (id) DO UPDATE SET name = ?2, slug = ?3;",
+                    params![game.id(), game.name(), game.slug()],
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Fixed width, so stored timestamps compare correctly as text.
+fn timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Reads a Discord id stored as text in a nullable column.
+fn optional_id(row: &Row, idx: i32) -> Result<Option<u64>> {
+    match row.column_type(idx) {
+        Ok(ValueType::Text) => Ok(Some(row.get::<String>(idx)?.parse::<u64>()?)),
+        Ok(ValueType::Null) => Ok(None),
+        other => Err(anyhow!(
+            "Expected field {idx} to be of type Text or Null. Was {:?}",
+            other
+        )),
+    }
+}
+
+/// The `guild_id` and `user_id` columns of deliveries and the outbox.
+fn recipient_ids(recipient: Recipient) -> (Option<String>, Option<String>) {
+    match recipient {
+        Recipient::Guild(guild) => (Some(guild.to_string()), None),
+        Recipient::User(user) => (None, Some(user.to_string())),
+    }
+}
+
+/// Reads a recipient from a `guild_id` column at `idx` followed by a `user_id`
+/// column.
+fn recipient(row: &Row, idx: i32) -> Result<Recipient> {
+    match (optional_id(row, idx)?, optional_id(row, idx + 1)?) {
+        (Some(guild), None) => Ok(Recipient::Guild(GuildId::new(guild))),
+        (None, Some(user)) => Ok(Recipient::User(UserId::new(user))),
+        _ => Err(anyhow!(
+            "Expected exactly one of fields {idx} and {} to be set",
+            idx + 1
+        )),
+    }
+}
+
+/// Forgets the failed deliveries of the guild, only of `game` if given, so
+/// their codes are queued again once the settings that made them fail changed.
+async fn retry_failed(client: &Connection, guild: GuildId, game: Option<Game>) -> Result<()> {
+    client
+        .execute(
+            "DELETE FROM deliveries WHERE guild_id = ?1 AND status = ?2 AND (?3 IS NULL OR code IN (SELECT id FROM codes WHERE game = ?3));",
+            params![
+                guild.to_string(),
+                DeliveryStatus::Failed.as_str(),
+                game.map(|game| game.id())
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+#[async_trait]
+impl Store for TursoDb {
+    async fn guilds(&self) -> Result<Vec<TursoGuild>> {
+        let client = self.connection().await?;
+        let mut rows = client
+            .query("SELECT * FROM guilds WHERE removed_at IS NULL;", ())
+            .await?;
+        let mut guilds = Vec::new();
+        while let Some(row) = rows.next()? {
+            guilds.push(TursoGuild::from_row(row)?);
+        }
+        Ok(guilds)
+    }
+
+    async fn guild(&self, guild: GuildId) -> Result<Option<TursoGuild>> {
+        let client = self.connection().await?;
+        let mut rows = client
+            .query(
+                "SELECT * FROM guilds WHERE guild_id = ?1 AND removed_at IS NULL;",
+                [guild.to_string()],
+            )
+            .await?;
+        if let Some(row) = rows.next()? {
+            Ok(Some(TursoGuild::from_row(row)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn try_add_guild(&self, guild: GuildId) -> Result<bool> {
+        let client = self.connection().await?;
+        // Only removed guilds are touched on conflict, restoring their settings
+        let res = client
+            .execute(
+                "INSERT INTO guilds (id, guild_id) VALUES (NULL, ?1) ON CONFLICT (guild_id) DO UPDATE SET removed_at = NULL WHERE removed_at IS NOT NULL;",
+                [guild.to_string()],
+            )
+            .await?;
+        if res == 1 {
+            warn!(guild=?&guild, "New guild joined. Adding to config");
+        }
+        Ok(res == 1)
+    }
+
+    async fn remove_guild(&self, guild: GuildId, at: DateTime<Utc>) -> Result<()> {
+        let client = self.connection().await?;
+        client
+            .execute(
+                "UPDATE guilds SET removed_at = ?2 WHERE guild_id = ?1 AND removed_at IS NULL;",
+                params![guild.to_string(), timestamp(at)],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn purge_removed_guilds(&self, before: DateTime<Utc>) -> Result<Vec<GuildId>> {
+        self.transaction(|tx| async move {
+            let mut guilds = Vec::new();
+            {
+                let mut rows = tx
+                    .query(
+                        "SELECT guild_id FROM guilds WHERE removed_at IS NOT NULL AND removed_at < ?1;",
+                        [timestamp(before)],
+                    )
+                    .await?;
+                while let Some(row) = rows.next()? {
+                    guilds.push(GuildId::new(row.get::<String>(0)?.parse::<u64>()?));
+                }
+            }
+            for guild in guilds.iter() {
+                // Deliveries reference the outbox, so they go first
+                for table in ["deliveries", "outbox", "subscriptions", "guilds"] {
+                    tx.execute(
+                        &format!("DELETE FROM {table} WHERE guild_id = ?1;"),
+                        [guild.to_string()],
+                    )
+                    .await?;
+                }
+            }
+            Ok(guilds)
+        })
+        .await
+    }
+
+    async fn set_guild_state(&self, guild: GuildId, enabled: bool) -> Result<()> {
+        if self.guild(guild).await?.is_none() && self.try_add_guild(guild).await? {
+            info!(id=?guild, "Discovered new guild!. Added to db");
+        }
+        let client = self.connection().await?;
+        let res = client
+            .execute(
+                "UPDATE guilds SET enabled = ?1 WHERE guild_id = ?2;",
+                params![enabled as i64, guild.to_string()],
+            )
+            .await?;
+        if res != 1 {
+            return Err(anyhow!("Update did not succeed. Affected rows: {}", res));
+        }
+        if enabled {
+            retry_failed(&client, guild, None).await?;
+        }
+        Ok(())
+    }
+
+    async fn set_guild_alert_role(&self, guild: GuildId, role: Option<RoleId>) -> Result<()> {
+        let client = self.connection().await?;
+        let res = client
+            .execute(
+                "UPDATE guilds SET alert_role = ?1 WHERE guild_id = ?2",
+                params![role.map(|id| i64::from(id)), guild.to_string()],
+            )
+            .await?;
+        if res != 1 {
+            return Err(anyhow!("Update did not succeed. Affected rows: {}", res));
+        }
+        retry_failed(&client, guild, None).await?;
+        Ok(())
+    }
+
+    async fn set_guild_manager_role(&self, guild: GuildId, role: Option<RoleId>) -> Result<()> {
+        let client = self.connection().await?;
+        let res = client
+            .execute(
+                "UPDATE guilds SET manager_role = ?1 WHERE guild_id = ?2",
+                params![role.map(|id| id.to_string()), guild.to_string()],
+            )
+            .await?;
+        if res != 1 {
+            return Err(anyhow!("Update did not succeed. Affected rows: {}", res));
+        }
+        Ok(())
+    }
+
+    async fn set_guild_alert_channel(
+        &self,
+        guild: GuildId,
+        channel: Option<ChannelId>,
+    ) -> Result<()> {
+        let client = self.connection().await?;
+        let res = client
+            .execute(
+                "UPDATE guilds SET alert_channel = ?1 WHERE guild_id = ?2",
+                params![channel.map(|id| i64::from(id)), guild.to_string()],
+            )
+            .await?;
+        if res != 1 {
+            return Err(anyhow!("Update did not succeed. Affected rows: {}", res));
+        }
+        retry_failed(&client, guild, None).await?;
+        Ok(())
+    }
+
+    async fn subscriptions(&self, guild: GuildId) -> Result<Vec<TursoSubscription>> {
+        let client = self.connection().await?;
+        let mut rows = client
+            .query(
+                "SELECT guild_id, game, alert_channel, alert_role, enabled FROM subscriptions WHERE guild_id = ?1 ORDER BY game;",
+                [guild.to_string()],
+            )
+            .await?;
+        let mut subscriptions = Vec::new();
+        while let Some(row) = rows.next()? {
+            subscriptions.push(TursoSubscription::from_row(row)?);
+        }
+        Ok(subscriptions)
+    }
+
+    async fn set_subscription_state(
+        &self,
+        guild: GuildId,
+        game: Game,
+        enabled: bool,
+    ) -> Result<()> {
+        let client = self.connection().await?;
+        client
+            .execute(
+                "INSERT INTO subscriptions (guild_id, game, enabled) VALUES (?1, ?2, ?3) ON CONFLICT (guild_id, game) DO UPDATE SET enabled = ?3;",
+                params![guild.to_string(), game.id(), enabled as i64],
+            )
+            .await?;
+        if enabled {
+            retry_failed(&client, guild, Some(game)).await?;
+        }
+        Ok(())
+    }
+
+    async fn set_subscription_channel(
+        &self,
+        guild: GuildId,
+        game: Game,
+        channel: Option<ChannelId>,
+    ) -> Result<()> {
+        let client = self.connection().await?;
+        client
+            .execute(
+                "INSERT INTO subscriptions (guild_id, game, alert_channel) VALUES (?1, ?2, ?3) ON CONFLICT (guild_id, game) DO UPDATE SET alert_channel = ?3;",
+                params![
+                    guild.to_string(),
+                    game.id(),
+                    channel.map(|id| id.to_string())
+                ],
+            )
+            .await?;
+        retry_failed(&client, guild, Some(game)).await?;
+        Ok(())
+    }
+
+    async fn set_subscription_role(
+        &self,
+        guild: GuildId,
+        game: Game,
+        role: Option<RoleId>,
+    ) -> Result<()> {
+        let client = self.connection().await?;
+        client
+            .execute(
+                "INSERT INTO subscriptions (guild_id, game, alert_role) VALUES (?1, ?2, ?3) ON CONFLICT (guild_id, game) DO UPDATE SET alert_role = ?3;",
+                params![guild.to_string(), game.id(), role.map(|id| id.to_string())],
+            )
+            .await?;
+        retry_failed(&client, guild, Some(game)).await?;
+        Ok(())
+    }
+
+    async fn forget_channel(&self, guild: GuildId, channel: ChannelId) -> Result<bool> {
+        let client = self.connection().await?;
+        // Each setting is cleared on its own, a partial update is still correct
+        let mut affected = 0;
+        for query in [
+            "UPDATE guilds SET alert_channel = NULL WHERE guild_id = ?1 AND alert_channel = ?2;",
+            "UPDATE subscriptions SET alert_channel = NULL WHERE guild_id = ?1 AND alert_channel = ?2;",
+        ] {
+            affected += client
+                .execute(query, params![guild.to_string(), channel.to_string()])
+                .await?;
+        }
+        Ok(affected > 0)
+    }
+
+    async fn forget_role(&self, guild: GuildId, role: RoleId) -> Result<bool> {
+        let client = self.connection().await?;
+        // Each setting is cleared on its own, a partial update is still correct
+        let mut affected = 0;
+        for query in [
+            "UPDATE guilds SET alert_role = NULL WHERE guild_id = ?1 AND alert_role = ?2;",
+            "UPDATE guilds SET manager_role = NULL WHERE guild_id = ?1 AND manager_role = ?2;",
+            "UPDATE subscriptions SET alert_role = NULL WHERE guild_id = ?1 AND alert_role = ?2;",
+        ] {
+            affected += client
+                .execute(query, params![guild.to_string(), role.to_string()])
+                .await?;
+        }
+        Ok(affected > 0)
+    }
+
+    async fn record_codes(&self, game: Game, new_codes: &[ScrapedCode]) -> Result<()> {
+        if new_codes.is_empty() {
+            // An empty scrape means the source is unavailable, not that every
+            // code expired
+            warn!(game=?game, "No codes to record. Keeping known codes");
+            return Ok(());
+        }
+
+        self.transaction(|tx| async move {
+            for code in new_codes {
+                let rewards = serde_json::to_string(&code.rewards)?;
+                let expires_at = code.expires_at.map(|date| date.to_string());
+                let mut exist = tx
+                    .query(
+                        "SELECT * FROM codes WHERE game = ?1 AND code = ?2;",
+                        params![game.id(), code.code.as_str()],
+                    )
+                    .await?;
+                if exist.next()?.is_none() {
+                    tx.execute(
+                        "INSERT INTO codes (id, code, valid, rewards, expires_at, first_seen, game, source) VALUES (NULL, ?1, 1, ?2, ?3, ?4, ?5, ?6);",
+                        params![
+                            code.code.as_str(),
+                            rewards,
+                            expires_at,
+                            Utc::now().to_rfc3339(),
+                            game.id(),
+                            code.source.as_deref()
+                        ],
+                    )
+                    .await?;
+                } else {
+                    // A code listed again by any source is valid again. Keep known
+                    // metadata if the source stopped listing it.
+                    tx.execute(
+                        "UPDATE codes SET valid = 1, rewards = COALESCE(?2, rewards), expires_at = COALESCE(?3, expires_at), source = COALESCE(source, ?4) WHERE code = ?1 AND game = ?5;",
+                        params![
+                            code.code.as_str(),
+                            (!code.rewards.is_empty()).then_some(rewards),
+                            expires_at,
+                            code.source.as_deref(),
+                            game.id()
+                        ],
+                    )
+                    .await?;
+                }
+            }
+
+            // ?1 is the game, the listed codes are bound from ?2 on
+            let placeholders = (0..new_codes.len())
+                .map(|i| format!("?{}", i + 2))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let mut values = vec![Value::from(game.id())];
+            values.extend(new_codes.iter().map(|code| Value::from(code.code.as_str())));
+            tx.execute(
+                &format!(
+                    "UPDATE codes SET valid = 0 WHERE game = ?1 AND code NOT IN ({placeholders});"
+                ),
+                Params::Positional(values),
+            )
+            .await?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn expire_codes(&self, game: Game) -> Result<()> {
+        self.connection()
+            .await?
+            .execute("UPDATE codes SET valid = 0 WHERE game = ?1;", [game.id()])
+            .await?;
+        Ok(())
+    }
+
+    async fn valid_codes(&self) -> Result<Vec<TursoCode>> {
+        let client = self.connection().await?;
+        let mut rows = client
+            .query("SELECT * FROM codes WHERE valid = 1 ORDER BY game, id;", ())
+            .await?;
+        let mut codes = Vec::new();
+        while let Some(row) = rows.next()? {
+            codes.push(TursoCode::from_row(row)?);
+        }
+        Ok(codes)
+    }
+
+    async fn pending_updates(&self) -> Result<Vec<GuildUpdate>> {
+        let client = self.connection().await?;
+        // Guilds without a subscription to the default game are subscribed to
+        // it implicitly
+        let mut rows = client
+            .query(
+                "SELECT c.*, g.guild_id AS target_guild, COALESCE(s.alert_channel, g.alert_channel) AS target_channel, COALESCE(s.alert_role, g.alert_role) AS target_role
+                FROM guilds g
+                JOIN (
+                    SELECT guild_id, game, alert_channel, alert_role, enabled FROM subscriptions
+                    UNION ALL
+                    SELECT guild_id, ?1, NULL, NULL, 1 FROM guilds
+                    WHERE guild_id NOT IN (SELECT guild_id FROM subscriptions WHERE game = ?1)
+                ) s ON s.guild_id = g.guild_id
+                JOIN codes c ON c.game = s.game
+                WHERE g.enabled = 1 AND g.removed_at IS NULL AND s.enabled = 1 AND c.valid = 1
+                AND COALESCE(s.alert_channel, g.alert_channel) IS NOT NULL
+                AND NOT EXISTS (SELECT 1 FROM deliveries d WHERE d.guild_id = g.guild_id AND d.code = c.id)
+                ORDER BY g.id, c.game, c.id;",
+                [Game::DEFAULT.id()],
+            )
+            .await?;
+        let mut updates: Vec<GuildUpdate> = Vec::new();
+        while let Some(row) = rows.next()? {
+            let guild = Recipient::Guild(GuildId::new(row.get::<String>(8)?.parse::<u64>()?));
+            let chan = optional_id(&row, 9)?.map(ChannelId::new);
+            let role = optional_id(&row, 10)?.map(RoleId::new);
+            let code = TursoCode::from_row(row)?;
+            match updates.last_mut() {
+                Some(update) if update.recipient == guild && update.game == code.game => {
+                    update.codes.get_or_insert_with(Vec::new).push(code);
+                }
+                _ => updates.push(GuildUpdate {
+                    recipient: guild,
+                    game: code.game,
+                    role,
+                    chan,
+                    enabled: true,
+                    codes: Some(vec![code]),
+                }),
+            }
+        }
+
+        let mut rows = client
+            .query(
+                "SELECT c.*, u.user_id AS target_user, u.dm_channel AS target_channel
+                FROM users u
+                JOIN codes c ON c.game = u.game
+                WHERE c.valid = 1
+                AND NOT EXISTS (SELECT 1 FROM deliveries d WHERE d.user_id = u.user_id AND d.code = c.id)
+                ORDER BY u.user_id, c.game, c.id;",
+                (),
+            )
+            .await?;
+        while let Some(row) = rows.next()? {
+            let user = Recipient::User(UserId::new(row.get::<String>(8)?.parse::<u64>()?));
+            let chan = optional_id(&row, 9)?.map(ChannelId::new);
+            let code = TursoCode::from_row(row)?;
+            match updates.last_mut() {
+                Some(update) if update.recipient == user && update.game == code.game => {
+                    update.codes.get_or_insert_with(Vec::new).push(code);
+                }
+                _ => updates.push(GuildUpdate {
+                    recipient: user,
+                    game: code.game,
+                    role: None,
+                    chan,
+                    enabled: true,
+                    codes: Some(vec![code]),
+                }),
+            }
+        }
+        Ok(updates)
+    }
+
+    async fn enqueue(&self, update: &GuildUpdate) -> Result<()> {
+        let (guild_id, user_id) = recipient_ids(update.recipient);
+        self.transaction(|tx| async move {
+            let outbox_id: i64 = {
+                let mut rows = tx
+                .query(
+                    "INSERT INTO outbox (guild_id, user_id, game, alert_channel, alert_role, next_attempt_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6) RETURNING id;",
+                    params![
+                        guild_id.clone(),
+                        user_id.clone(),
+                        update.game.id(),
+                        update.chan.map(|id| id.to_string()),
+                        update.role.map(|id| id.to_string()),
+                        timestamp(Utc::now())
+                    ],
+                )
+                .await?;
+                let Some(row) = rows.next()? else {
+                    return Err(anyhow!("Insert did not return the outbox id"));
+                };
+                row.get(0)?
+            };
+            let conflict = match update.recipient {
+                Recipient::Guild(_) => "guild_id",
+                Recipient::User(_) => "user_id",
+            };
+            for code in update.codes.iter().flatten() {
+                tx.execute(
+                    &format!("INSERT INTO deliveries (guild_id, user_id, code, status, outbox_id) VALUES (?1, ?2, ?3, ?4, ?5) ON CONFLICT ({conflict}, code) DO UPDATE SET message_id = NULL, sent_at = NULL, status = ?4, outbox_id = ?5;"),
+                    params![
+                        guild_id.clone(),
+                        user_id.clone(),
+                        code.id,
+                        DeliveryStatus::Queued.as_str(),
+                        outbox_id
+                    ],
+                )
+                .await?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn due_outbox(&self, now: DateTime<Utc>) -> Result<Vec<OutboxEntry>> {
+        let client = self.connection().await?;
+        let mut rows = client
+            .query(
+                "SELECT id, guild_id, user_id, game, alert_channel, alert_role, attempts FROM outbox WHERE status = 'pending' AND next_attempt_at <= ?1 ORDER BY id;",
+                [timestamp(now)],
+            )
+            .await?;
+        let mut entries = Vec::new();
+        while let Some(row) = rows.next()? {
+            let game_id: i64 = row.get(3)?;
+            entries.push(OutboxEntry {
+                id: row.get(0)?,
+                attempts: row.get(6)?,
+                update: GuildUpdate {
+                    recipient: recipient(&row, 1)?,
+                    game: Game::from_id(game_id)
+                        .ok_or_else(|| anyhow!("Unknown game {game_id}"))?,
+                    chan: optional_id(&row, 4)?.map(ChannelId::new),
+                    role: optional_id(&row, 5)?.map(RoleId::new),
+                    codes: None,
+                    enabled: true,
+                },
+            });
+        }
+
+        for entry in entries.iter_mut() {
+            let mut rows = client
+                .query(
+                    "SELECT c.* FROM deliveries d JOIN codes c ON c.id = d.code WHERE d.outbox_id = ?1 ORDER BY c.id;",
+                    [entry.id],
+                )
+                .await?;
+            let mut codes = Vec::new();
+            while let Some(row) = rows.next()? {
+                codes.push(TursoCode::from_row(row)?);
+            }
+            entry.update.codes = Some(codes);
+        }
+        Ok(entries)
+    }
+
+    async fn complete_outbox(&self, id: i64, message: Option<MessageId>) -> Result<()> {
+        self.transaction(|tx| async move {
+            tx.execute(
+                "UPDATE deliveries SET status = ?2, message_id = ?3, sent_at = ?4, outbox_id = NULL, channel_id = (SELECT alert_channel FROM outbox WHERE id = ?1) WHERE outbox_id = ?1;",
+                params![
+                    id,
+                    DeliveryStatus::Sent.as_str(),
+                    message.map(|id| id.to_string()),
+                    Utc::now().to_rfc3339()
+                ],
+            )
+            .await?;
+            tx.execute("DELETE FROM outbox WHERE id = ?1;", [id])
+                .await?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn retry_outbox(
+        &self,
+        id: i64,
+        next_attempt_at: DateTime<Utc>,
+        error: &str,
+    ) -> Result<()> {
+        let client = self.connection().await?;
+        client
+            .execute(
+                "UPDATE outbox SET attempts = attempts + 1, next_attempt_at = ?2, last_error = ?3 WHERE id = ?1;",
+                params![id, timestamp(next_attempt_at), error],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn dead_letter_outbox(&self, id: i64, error: &str) -> Result<()> {
+        self.transaction(|tx| async move {
+            tx.execute(
+                "UPDATE outbox SET attempts = attempts + 1, status = 'dead', last_error = ?2 WHERE id = ?1;",
+                params![id, error],
+            )
+            .await?;
+            tx.execute(
+                "UPDATE deliveries SET status = ?2 WHERE outbox_id = ?1;",
+                params![id, DeliveryStatus::Failed.as_str()],
+            )
+            .await?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delivery_stats(&self, guild: GuildId) -> Result<DeliveryStats> {
+        let client = self.connection().await?;
+        let mut stats = DeliveryStats::default();
+        let mut rows = client
+            .query(
+                "SELECT c.*, d.sent_at FROM deliveries d JOIN codes c ON c.id = d.code WHERE d.guild_id = ?1 AND d.status = ?2 ORDER BY d.sent_at IS NULL, d.sent_at DESC, c.id DESC LIMIT 1;",
+                params![guild.to_string(), DeliveryStatus::Sent.as_str()],
+            )
+            .await?;
+        if let Some(row) = rows.next()? {
+            let sent_at = match row.column_type(8)? {
+                ValueType::Text => Some(parse_timestamp(&row.get::<String>(8)?)?),
+                _ => None,
+            };
+            stats.last_sent = Some((TursoCode::from_row(row)?, sent_at));
+        }
+
+        let mut rows = client
+            .query(
+                "SELECT status, COUNT(*) FROM deliveries WHERE guild_id = ?1 GROUP BY status;",
+                [guild.to_string()],
+            )
+            .await?;
+        while let Some(row) = rows.next()? {
+            let count: i64 = row.get(1)?;
+            match row.get::<String>(0)?.as_str() {
+                status if status == DeliveryStatus::Queued.as_str() => stats.queued = count,
+                status if status == DeliveryStatus::Failed.as_str() => stats.failed = count,
+                _ => {}
+            }
+        }
+        Ok(stats)
+    }
+
+    async fn subscribe_user(
+        &self,
+        user: UserId,
+        game: Game,
+        dm_channel: ChannelId,
+    ) -> Result<bool> {
+        let client = self.connection().await?;
+        let res = client
+            .execute(
+                "INSERT INTO users (user_id, game, dm_channel) VALUES (?1, ?2, ?3) ON CONFLICT (user_id, game) DO NOTHING;",
+                params![user.to_string(), game.id(), dm_channel.to_string()],
+            )
+            .await?;
+        Ok(res == 1)
+    }
+
+    async fn unsubscribe_user(&self, user: UserId, game: Option<Game>) -> Result<bool> {
+        self.transaction(|tx| async move {
+            let res = if let Some(game) = game {
+                tx.execute(
+                    "DELETE FROM users WHERE user_id = ?1 AND game = ?2;",
+                    params![user.to_string(), game.id()],
+                )
+                .await?
+            } else {
+                tx.execute("DELETE FROM users WHERE user_id = ?1;", [user.to_string()])
+                    .await?
+            };
+            let mut left = tx
+                .query(
+                    "SELECT 1 FROM users WHERE user_id = ?1;",
+                    [user.to_string()],
+                )
+                .await?;
+            if left.next()?.is_none() {
+                // Nothing about the user is kept once they are gone
+                for table in ["deliveries", "outbox"] {
+                    tx.execute(
+                        &format!("DELETE FROM {table} WHERE user_id = ?1;"),
+                        [user.to_string()],
+                    )
+                    .await?;
+                }
+            }
+            Ok(res > 0)
+        })
+        .await
+    }
+
+    async fn stale_alerts(&self) -> Result<Vec<SentAlert>> {
+        let client = self.connection().await?;
+        let mut rows = client
+            .query(
+                "SELECT DISTINCT d.guild_id, d.user_id, d.channel_id, d.message_id FROM deliveries d JOIN codes c ON c.id = d.code
+                WHERE d.status = ?1 AND d.message_id IS NOT NULL AND d.channel_id IS NOT NULL AND d.shown_expired != (c.valid = 0)
+                ORDER BY d.message_id;",
+                [DeliveryStatus::Sent.as_str()],
+            )
+            .await?;
+        let mut alerts = Vec::new();
+        while let Some(row) = rows.next()? {
+            alerts.push((
+                recipient(&row, 0)?,
+                ChannelId::new(row.get::<String>(2)?.parse::<u64>()?),
+                MessageId::new(row.get::<String>(3)?.parse::<u64>()?),
+            ));
+        }
+
+        let mut stale = Vec::new();
+        for (recipient, channel, message) in alerts {
+            let mut rows = client
+                .query(
+                    "SELECT c.* FROM deliveries d JOIN codes c ON c.id = d.code WHERE d.message_id = ?1 ORDER BY c.id;",
+                    [message.to_string()],
+                )
+                .await?;
+            let mut codes = Vec::new();
+            while let Some(row) = rows.next()? {
+                codes.push(TursoCode::from_row(row)?);
+            }
+            stale.push(SentAlert {
+                recipient,
+                channel,
+                message,
+                game: codes[0].game,
+                codes,
+            });
+        }
+        Ok(stale)
+    }
+
+    async fn mark_alert_updated(&self, message: MessageId) -> Result<()> {
+        let client = self.connection().await?;
+        client
+            .execute(
+                "UPDATE deliveries SET shown_expired = (SELECT valid = 0 FROM codes WHERE codes.id = deliveries.code) WHERE message_id = ?1;",
+                [message.to_string()],
+            )
+            .await?;
+        Ok(())
+    }
+}
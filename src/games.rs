@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+
+/// The HoYoverse titles the bot tracks codes for.
+///
+/// The ids match the rows of the `games` table, which is seeded from this enum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Game {
+    StarRail,
+    Genshin,
+    ZenlessZoneZero,
+}
+
+impl Game {
+    pub const ALL: [Game; 3] = [Game::StarRail, Game::Genshin, Game::ZenlessZoneZero];
+
+    /// Game alerts go out for in guilds that never configured any game.
+    pub const DEFAULT: Game = Game::StarRail;
+
+    pub fn id(&self) -> i64 {
+        match self {
+            Game::StarRail => 1,
+            Game::Genshin => 2,
+            Game::ZenlessZoneZero => 3,
+        }
+    }
+
+    pub fn from_id(id: i64) -> Option<Self> {
+        Self::ALL.into_iter().find(|game| game.id() == id)
+    }
+
+    /// Short name used in command options.
+    pub fn slug(&self) -> &'static str {
+        match self {
+            Game::StarRail => "hsr",
+            Game::Genshin => "genshin",
+            Game::ZenlessZoneZero => "zzz",
+        }
+    }
+
+    pub fn from_slug(slug: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|game| game.slug() == slug)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Game::StarRail => "Honkai: Star Rail",
+            Game::Genshin => "Genshin Impact",
+            Game::ZenlessZoneZero => "Zenless Zone Zero",
+        }
+    }
+
+    /// Shown as the thumbnail of alert embeds. Discord does not render
+    /// `.ico` thumbnails, so the site icons are fetched as PNG.
+    pub fn icon_url(&self) -> &'static str {
+        match self {
+            Game::StarRail => "https://www.google.com/s2/favicons?domain=hsr.hoyoverse.com&sz=128",
+            Game::Genshin => {
+                "https://www.google.com/s2/favicons?domain=genshin.hoyoverse.com&sz=128"
+            }
+            Game::ZenlessZoneZero => {
+                "https://www.google.com/s2/favicons?domain=zenless.hoyoverse.com&sz=128"
+            }
+        }
+    }
+
+    /// Accent colour of alert embeds.
+    pub fn colour(&self) -> (u8, u8, u8) {
+        match self {
+            Game::StarRail => (98, 87, 178),
+            Game::Genshin => (236, 229, 216),
+            Game::ZenlessZoneZero => (250, 200, 30),
+        }
+    }
+
+    pub fn redeem_url(&self, code: &str) -> String {
+        match self {
+            Game::StarRail => format!("https://hsr.hoyoverse.com/gift?code={code}"),
+            Game::Genshin => format!("https://genshin.hoyoverse.com/en/gift?code={code}"),
+            Game::ZenlessZoneZero => {
+                format!("https://zenless.hoyoverse.com/redemption?code={code}")
+            }
+        }
+    }
+}
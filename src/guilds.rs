@@ -0,0 +1,446 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use futures::{stream, StreamExt};
+use serenity::all::{
+    ChannelId, Context, CreateMessage, Guild, GuildChannel, GuildId, PartialGuild, Permissions,
+    Role, RoleId,
+};
+use serenity::http::HttpError;
+
+use crate::db::{Store, TursoGuild, TursoSubscription};
+use crate::games::Game;
+
+/// Guilds validated at the same time.
+const VALIDATION_CONCURRENCY: usize = 8;
+/// Days the settings of a guild are kept after the bot was removed from it.
+pub const RETENTION_DAYS: i64 = 30;
+/// Discord's error codes for guilds the bot is not a member of anymore.
+const UNKNOWN_GUILD: isize = 10004;
+const MISSING_ACCESS: isize = 50001;
+
+#[derive(Debug)]
+pub enum InvalidInfo {
+    Channel(ChannelId),
+    Role(RoleId),
+    Both(ChannelId, RoleId),
+}
+
+pub async fn send_to_all_guilds(db: &dyn Store, message: String, ctx: &Context) -> Result<()> {
+    let all_guilds = db.guilds().await?;
+
+    for guild in all_guilds {
+        if let Some(channel_id) = guild.alert_channel {
+            if let Ok(guild) = get_guild(&guild.guild_id, ctx).await {
+                if let Some((_, channel)) = guild
+                    .channels(&ctx.http)
+                    .await?
+                    .iter()
+                    .find(|(&id, _)| id == channel_id)
+                {
+                    channel
+                        .send_message(&ctx.http, CreateMessage::new().content(&message))
+                        .await?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Something keeping a subscription from receiving alerts.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    NoChannel(Game),
+    UnknownChannel(Game, ChannelId),
+    UnknownRole(Game, RoleId),
+    /// The bot lacks these permissions in the alert channel
+    MissingPermissions(Game, ChannelId, Permissions),
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::NoChannel(game) => write!(f, "{}: no alert channel set", game.name()),
+            Problem::UnknownChannel(game, channel) => write!(
+                f,
+                "{}: the alert channel (id={channel}) does not exist anymore",
+                game.name()
+            ),
+            Problem::UnknownRole(game, role) => write!(
+                f,
+                "{}: the alert role (id={role}) does not exist anymore",
+                game.name()
+            ),
+            Problem::MissingPermissions(game, channel, missing) => write!(
+                f,
+                "{}: missing {} in <#{channel}>",
+                game.name(),
+                missing.get_permission_names().join(", ")
+            ),
+        }
+    }
+}
+
+/// Every enabled subscription needs a channel to post to and, if it pings, an
+/// existing role.
+fn subscription_problems(
+    guild: &TursoGuild,
+    subscriptions: &[TursoSubscription],
+    g: &PartialGuild,
+    channels: &HashMap<ChannelId, GuildChannel>,
+) -> Vec<Problem> {
+    let mut problems = vec![];
+    for subscription in subscriptions.iter().filter(|sub| sub.enabled == 1) {
+        match subscription.channel(guild) {
+            None => problems.push(Problem::NoChannel(subscription.game)),
+            Some(channel) if !channels.contains_key(&channel) => {
+                problems.push(Problem::UnknownChannel(subscription.game, channel))
+            }
+            Some(_) => {}
+        }
+        if let Some(role) = subscription.role(guild) {
+            if !g.roles.contains_key(&role) {
+                problems.push(Problem::UnknownRole(subscription.game, role));
+            }
+        }
+    }
+    problems
+}
+
+/// Permissions alerts need in their channel. Pinging a role that is not
+/// mentionable additionally needs [`Permissions::MENTION_EVERYONE`].
+fn required_permissions(role: Option<&Role>) -> Permissions {
+    let mut required =
+        Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES | Permissions::EMBED_LINKS;
+    if role.is_some_and(|role| !role.mentionable) {
+        required |= Permissions::MENTION_EVERYONE;
+    }
+    required
+}
+
+/// Why the bot cannot give members the alert role or take it away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoleProblem {
+    UnknownRole(RoleId),
+    MissingManageRoles,
+    /// The role is not below the highest role of the bot
+    AboveBot(RoleId),
+}
+
+impl Display for RoleProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RoleProblem::UnknownRole(role) => write!(
+                f,
+                "The alert role (id={role}) does not exist anymore. Ask a manager to set it again using `/alert-role`."
+            ),
+            RoleProblem::MissingManageRoles => f.write_str(
+                "I am missing the Manage Roles permission. Ask a manager to grant it to me.",
+            ),
+            RoleProblem::AboveBot(role) => write!(
+                f,
+                "The alert role <@&{role}> is not below my highest role, so I cannot assign it. Ask a manager to move my role above it."
+            ),
+        }
+    }
+}
+
+/// Discord only lets bots with Manage Roles assign roles below their own
+/// highest role.
+fn role_problem(
+    bot_permissions: Permissions,
+    bot_highest: Option<&Role>,
+    role: &Role,
+) -> Option<RoleProblem> {
+    if !bot_permissions.manage_roles() {
+        return Some(RoleProblem::MissingManageRoles);
+    }
+    match bot_highest {
+        Some(highest) if role.position < highest.position => None,
+        _ => Some(RoleProblem::AboveBot(role.id)),
+    }
+}
+
+/// Checks whether the bot can assign `role` to members of the guild.
+pub async fn check_role(
+    ctx: &Context,
+    guild: GuildId,
+    role: RoleId,
+) -> Result<Option<RoleProblem>> {
+    let g = get_guild(&guild, ctx).await?;
+    let Some(role) = g.roles.get(&role) else {
+        return Ok(Some(RoleProblem::UnknownRole(role)));
+    };
+    let bot_id = ctx.cache.current_user().id;
+    let bot = g.member(&ctx.http, bot_id).await?;
+    let highest = bot.roles.iter().filter_map(|id| g.roles.get(id)).max();
+    Ok(role_problem(g.member_permissions(&bot), highest, role))
+}
+
+/// Runs the checks of the periodic validation for one guild and additionally
+/// checks the permissions of the bot in every alert channel.
+pub async fn check_guild(
+    db: &dyn Store,
+    guild: &TursoGuild,
+    ctx: &Context,
+) -> Result<Vec<Problem>> {
+    let g = get_guild(&guild.guild_id, ctx).await?;
+    let channels = g.channels(&ctx.http).await?;
+    let subscriptions = db.guild_subscriptions(guild.guild_id).await?;
+    let mut problems = subscription_problems(guild, &subscriptions, &g, &channels);
+
+    let bot_id = ctx.cache.current_user().id;
+    let bot = g.member(&ctx.http, bot_id).await?;
+    for subscription in subscriptions.iter().filter(|sub| sub.enabled == 1) {
+        let Some(channel) = subscription.channel(guild).and_then(|id| channels.get(&id)) else {
+            continue;
+        };
+        let role = subscription.role(guild).and_then(|id| g.roles.get(&id));
+        let missing = required_permissions(role) - g.user_permissions_in(channel, &bot);
+        if !missing.is_empty() {
+            problems.push(Problem::MissingPermissions(
+                subscription.game,
+                channel.id,
+                missing,
+            ));
+        }
+    }
+    Ok(problems)
+}
+
+async fn validate_guild(
+    db: &dyn Store,
+    guild: &TursoGuild,
+    ctx: &Context,
+) -> Result<Option<(GuildId, InvalidInfo)>> {
+    if guild.enabled == 0 {
+        return Ok(None);
+    }
+    let g = match get_guild(&guild.guild_id, ctx).await {
+        Ok(g) => g,
+        Err(err) if is_removed(&err) => {
+            // The bot was removed while it was offline
+            db.remove_guild(guild.guild_id, Utc::now()).await?;
+            warn!(
+                id = ?guild.guild_id,
+                "Bot is not a member of the guild anymore. Removing it from known guilds."
+            );
+            return Ok(None);
+        }
+        Err(err) => {
+            warn!(id = ?guild.guild_id, reason = err.to_string(), "Could not get guild. Skipping it");
+            return Ok(None);
+        }
+    };
+    let channels = g.channels(&ctx.http).await?;
+    let subscriptions = db.guild_subscriptions(guild.guild_id).await?;
+    let mut invalid_channel = None;
+    let mut invalid_role = None;
+    for problem in subscription_problems(guild, &subscriptions, &g, &channels) {
+        // Deleted channels and roles are unset, so the guild is only told once
+        match problem {
+            Problem::UnknownChannel(_, channel) => {
+                if db.forget_channel(guild.guild_id, channel).await? {
+                    invalid_channel = Some(channel);
+                }
+            }
+            Problem::UnknownRole(_, role) => {
+                if db.forget_role(guild.guild_id, role).await? {
+                    invalid_role = Some(role);
+                }
+            }
+            // Codes stay pending until a channel is set
+            Problem::NoChannel(_) | Problem::MissingPermissions(..) => {}
+        }
+    }
+    let info = match (invalid_channel, invalid_role) {
+        (Some(channel), Some(role)) => InvalidInfo::Both(channel, role),
+        (Some(channel), None) => InvalidInfo::Channel(channel),
+        (None, Some(role)) => InvalidInfo::Role(role),
+        (None, None) => return Ok(None),
+    };
+    Ok(Some((guild.guild_id, info)))
+}
+
+fn is_removed(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<serenity::Error>(),
+        Some(serenity::Error::Http(HttpError::UnsuccessfulRequest(response)))
+            if response.error.code == UNKNOWN_GUILD || response.error.code == MISSING_ACCESS
+    )
+}
+
+/// Deletes the data of guilds the bot was removed from more than
+/// [`RETENTION_DAYS`] ago.
+pub async fn purge_removed(db: &dyn Store) -> Result<()> {
+    let before = Utc::now() - chrono::Duration::days(RETENTION_DAYS);
+    for guild in db.purge_removed_guilds(before).await? {
+        info!(guild=?guild, "Purged data of removed guild");
+    }
+    Ok(())
+}
+
+/// Checks the alert settings of every guild, at most
+/// [`VALIDATION_CONCURRENCY`] at a time.
+pub async fn validate_info(db: &dyn Store, ctx: &Context) -> Result<Vec<(GuildId, InvalidInfo)>> {
+    let guilds = db.guilds().await?;
+    let checks: Vec<_> = guilds
+        .iter()
+        .map(|guild| validate_guild(db, guild, ctx))
+        .collect();
+    let results: Vec<Result<Option<(GuildId, InvalidInfo)>>> = stream::iter(checks)
+        .buffer_unordered(VALIDATION_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut invalid_guilds: Vec<(GuildId, InvalidInfo)> = vec![];
+    for result in results {
+        if let Some(invalid) = result? {
+            invalid_guilds.push(invalid);
+        }
+    }
+
+    Ok(invalid_guilds)
+}
+
+pub async fn alert_guild_invalid_info(
+    ctx: &Context,
+    reason: &(GuildId, InvalidInfo),
+) -> Result<()> {
+    let default_chan = get_default_channel(reason.0, &ctx).await?;
+    warn!(guild=?&reason.0, invalid=?&reason.1, "Guild has invalid info");
+    match reason.1 {
+        InvalidInfo::Channel(chan_id) => {
+            alert_invalid_channel(&ctx, chan_id, &default_chan).await?;
+        }
+        InvalidInfo::Role(role_id) => {
+            alert_invalid_role(&ctx, role_id, &default_chan).await?;
+        }
+        InvalidInfo::Both(chan_id, role_id) => {
+            alert_invalid_channel(&ctx, chan_id, &default_chan).await?;
+            alert_invalid_role(&ctx, role_id, &default_chan).await?;
+        }
+    }
+    tracing::info!(
+        "Sent alert matching {:#?} to guild {}",
+        (*reason).1,
+        (*reason).0
+    );
+    Ok(())
+}
+
+/// Unsets the deleted channel in the settings of its guild and tells the guild.
+/// Discord sends the event once, so the guild is told once.
+pub async fn channel_deleted(db: &dyn Store, ctx: &Context, channel: &GuildChannel) -> Result<()> {
+    if !db.forget_channel(channel.guild_id, channel.id).await? {
+        return Ok(());
+    }
+    warn!(guild=?channel.guild_id, channel=?channel.id, "Alert channel was deleted");
+    let default_chan = get_default_channel(channel.guild_id, ctx).await?;
+    default_chan.send_message(&ctx.http, CreateMessage::new().content(format!("The alert channel #{} was deleted. Alerts are paused until a new channel is set using `/alert-channel`.", channel.name))).await?;
+    Ok(())
+}
+
+/// Unsets the deleted role in the settings of its guild and tells the guild.
+pub async fn role_deleted(
+    db: &dyn Store,
+    ctx: &Context,
+    guild: GuildId,
+    role: RoleId,
+    data: Option<&Role>,
+) -> Result<()> {
+    if !db.forget_role(guild, role).await? {
+        return Ok(());
+    }
+    warn!(guild=?guild, role=?role, "Role used by the bot was deleted");
+    let name = data.map_or_else(|| format!("(id={role})"), |data| format!("`{}`", data.name));
+    let default_chan = get_default_channel(guild, ctx).await?;
+    default_chan.send_message(&ctx.http, CreateMessage::new().content(format!("The role {name} used by the bot was deleted and removed from its settings. Set a new one using `/alert-role` or `/manager-role`."))).await?;
+    Ok(())
+}
+
+async fn alert_invalid_channel(
+    ctx: &Context,
+    chan_id: ChannelId,
+    default_chan: &GuildChannel,
+) -> Result<()> {
+    default_chan.send_message(&ctx.http, CreateMessage::new().content(format!("The channel (id={}) you set for the alerts does not exist anymore and was removed from the settings. Alerts are paused until a new channel is set using `/alert-channel`.", chan_id))).await?;
+    Ok(())
+}
+
+async fn alert_invalid_role(
+    ctx: &Context,
+    role_id: RoleId,
+    default_chan: &GuildChannel,
+) -> Result<()> {
+    default_chan.send_message(&ctx.http, CreateMessage::new().content(format!("The role (id={}) you set for the alerts does not exist anymore and was removed from the settings. Set a new one using `/alert-role`.", role_id))).await?;
+    Ok(())
+}
+
+async fn get_default_channel(guild_id: GuildId, ctx: &Context) -> Result<GuildChannel> {
+    return if let Ok(guild) = get_guild(&guild_id, &ctx).await {
+        return if let Ok(channels) = guild.channels(&ctx.http).await {
+            let default_chan: GuildChannel;
+            if let Some(system_channel_id) = guild.system_channel_id {
+                default_chan = channels
+                    .iter()
+                    .find(|(id, _)| **id == system_channel_id)
+                    .expect("Should exist")
+                    .1
+                    .clone();
+            } else {
+                if let Some((_, first_channel)) = channels.iter().find(|_| true) {
+                    default_chan = first_channel.clone();
+                } else {
+                    return Err(anyhow!("Could not get any channel for guild: {}", guild_id));
+                }
+            }
+            return Ok(default_chan);
+        } else {
+            Err(anyhow!("Could not get channels for guild: {}", guild_id))
+        };
+    } else {
+        Err(anyhow!("Could not retrieve info for guild {}", guild_id))
+    };
+}
+
+pub async fn get_guild(id: &GuildId, ctx: &Context) -> Result<PartialGuild> {
+    Ok(Guild::get(&ctx.http, id).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use serenity::all::{Permissions, Role, RoleId};
+
+    use super::{role_problem, RoleProblem};
+
+    fn role(id: u64, position: u16) -> Role {
+        let mut role = Role::default();
+        role.id = RoleId::new(id);
+        role.position = position;
+        role
+    }
+
+    #[test]
+    fn roles_need_to_be_below_the_bot() {
+        let bot = role(1, 5);
+        let manage = Permissions::MANAGE_ROLES;
+        assert_eq!(role_problem(manage, Some(&bot), &role(2, 4)), None);
+        assert_eq!(
+            role_problem(manage, Some(&bot), &role(2, 5)),
+            Some(RoleProblem::AboveBot(RoleId::new(2)))
+        );
+        assert_eq!(
+            role_problem(manage, None, &role(2, 0)),
+            Some(RoleProblem::AboveBot(RoleId::new(2)))
+        );
+        assert_eq!(
+            role_problem(Permissions::SEND_MESSAGES, Some(&bot), &role(2, 4)),
+            Some(RoleProblem::MissingManageRoles)
+        );
+    }
+}
@@ -1,5 +1,11 @@
-use anyhow::{anyhow, Result};
-use serenity::all::{CreateMessage, Guild, GuildId, PartialGuild};
+use std::collections::HashMap;
+
+use anyhow::Result;
+use chrono::Utc;
+use serenity::all::{
+    CreateMessage, Guild, GuildChannel, GuildId, Message, PartialGuild, Role, RoleId,
+    UnavailableGuild, UserId,
+};
 use serenity::{
     all::{Interaction, Ready},
     async_trait,
@@ -7,82 +13,119 @@ use serenity::{
     client::{Context, EventHandler},
 };
 
-use crate::commands::CreateCommandVecExt;
-use crate::db::{GuildUpdate, TursoDb};
-use crate::{commands, DB};
+use crate::commands::{reply, CreateCommandVecExt};
+use crate::db::Store;
+use crate::games::Game;
+use crate::scraper::{ScrapedCode, ScraperEvent, SourceCodes};
+use crate::{commands, guilds, outbox, DB};
 
 pub struct Handler {
     pub admin: String,
 }
 
 impl Handler {
-    async fn run_alerts(ctx: Context) {
+    async fn run_alerts(ctx: Context, admin: String) {
+        // Latest report of every source. Codes are only considered expired once
+        // no source lists them anymore.
+        let mut latest: HashMap<&'static str, (Game, Vec<ScrapedCode>)> = HashMap::new();
+        // Deleted channels and roles are handled as they are deleted. This
+        // catches up on what changed while the bot was offline.
+        info!("Validating guild information");
+        Self::validate_info(&ctx, DB.read().await.as_deref().unwrap()).await;
         loop {
-            info!("Validating guild information");
-
-            Self::validate_info(&ctx, DB.read().await.as_ref().unwrap()).await;
+            if let Err(err) = guilds::purge_removed(DB.read().await.as_deref().unwrap()).await {
+                error!(reason = err.to_string(), "Could not purge removed guilds");
+            }
             info!("Waiting for current codes from scaper");
-            let new_codes = crate::CODE_CHAN.lock().await.as_mut().unwrap().recv().await;
-            if let Some(codes) = new_codes {
-                if let Err(err) = Self::handle_new_codes(&ctx, &codes).await {
-                    error!(reason = err.to_string(), "Failed to handle new codes")
+            let event = crate::CODE_CHAN.lock().await.as_mut().unwrap().recv().await;
+            match event {
+                Some(ScraperEvent::Codes(SourceCodes {
+                    source,
+                    game,
+                    codes,
+                })) => Self::update_source(&mut latest, source, game, codes).await,
+                Some(ScraperEvent::NoCodes { source, game }) => {
+                    Self::update_source(&mut latest, source, game, vec![]).await
+                }
+                Some(ScraperEvent::Degraded { source, reason }) => {
+                    warn!(source, reason, "Code source degraded");
+                    Self::notify_admin(
+                        &ctx,
+                        &admin,
+                        format!("Code source `{source}` is degraded and will be retried later: {reason}"),
+                    )
+                    .await;
+                }
+                Some(ScraperEvent::Recovered { source }) => {
+                    info!(source, "Code source recovered");
+                    Self::notify_admin(&ctx, &admin, format!("Code source `{source}` recovered"))
+                        .await;
+                }
+                None => {
+                    error!("All scrapers stopped. No more codes will be received");
+                    return;
                 }
             }
         }
     }
 
-    async fn handle_new_codes(ctx: &Context, codes: &Vec<String>) -> Result<()> {
-        let db_opt = DB.read().await;
-        let db = db_opt.as_ref().unwrap();
-        for guild_diff in db.diff_guild_codes(codes, ctx).await? {
-            if let Err(err) = Self::send_new_codes(&guild_diff.1, &ctx).await {
-                error!(reason=err.to_string(), guild=?guild_diff.0, "Could not send codes");
-            } else {
-                info!(guild=?guild_diff.0, "Sent codes to guild");
-                db.set_codes_sent(guild_diff.0, guild_diff.1.codes).await?;
-            }
+    /// Stores the latest report of `source` and records the codes listed by
+    /// any source of its game.
+    async fn update_source(
+        latest: &mut HashMap<&'static str, (Game, Vec<ScrapedCode>)>,
+        source: &'static str,
+        game: Game,
+        codes: Vec<ScrapedCode>,
+    ) {
+        latest.insert(source, (game, codes));
+        let mut codes: Vec<ScrapedCode> = latest
+            .values()
+            .filter(|(source_game, _)| *source_game == game)
+            .flat_map(|(_, codes)| codes)
+            .cloned()
+            .collect();
+        codes.sort_by(|a, b| a.code.cmp(&b.code));
+        codes.dedup_by(|a, b| a.code == b.code);
+        if let Err(err) = Self::handle_new_codes(game, &codes).await {
+            error!(reason = err.to_string(), "Failed to handle new codes")
         }
-        Ok(())
     }
 
-    async fn send_new_codes(update: &GuildUpdate, ctx: &Context) -> Result<()> {
-        if !update.has_codes() {
-            info!(guild=?update.id, "No new codes to send");
-            return Ok(());
-        }
-        let header = if let Some(role) = update.role {
-            format!("New Star Rail codes available <@&{role}>")
-        } else {
-            "New Star Rail codes available".to_string()
+    async fn notify_admin(ctx: &Context, admin: &str, message: String) {
+        let Ok(admin_id) = admin.parse::<u64>() else {
+            error!(admin, "Admin is not a valid user id");
+            return;
         };
+        if let Err(err) = UserId::new(admin_id)
+            .direct_message(&ctx, CreateMessage::new().content(message))
+            .await
+        {
+            error!(reason = err.to_string(), "Could not notify admin");
+        }
+    }
 
-        let body = update
-            .codes
-            .as_ref()
-            .unwrap()
-            .iter()
-            .map(|code| {
-                format!(
-                    "> [{0}](https://hsr.hoyoverse.com/gift?code={0})",
-                    code.code
-                )
-            })
-            .fold(header, |acc, elem| acc + "\n" + elem.as_str());
-        let Some(alert_chan) = update.chan else {
-            return Err(anyhow!("No alert channel set"));
-        };
-        alert_chan
-            .send_message(&ctx.http, CreateMessage::new().content(body))
-            .await?;
-        info!(guild=?update.id, "Sent codes to guild");
+    async fn handle_new_codes(game: Game, codes: &Vec<ScrapedCode>) -> Result<()> {
+        let db_opt = DB.read().await;
+        let db = db_opt.as_deref().unwrap();
+        if codes.is_empty() {
+            // No source of the game lists any code anymore
+            db.expire_codes(game).await?;
+        } else {
+            db.record_codes(game, codes).await?;
+        }
+        for update in db.pending_updates().await? {
+            db.enqueue(&update).await?;
+            info!(recipient=?update.recipient, game=?update.game, "Queued codes");
+        }
+        outbox::wake();
         Ok(())
     }
 
-    async fn validate_info(ctx: &Context, db: &TursoDb) {
-        match db.validate_info(&ctx).await {
+    async fn validate_info(ctx: &Context, db: &dyn Store) {
+        match guilds::validate_info(db, &ctx).await {
             Ok(data) => {
                 for reason in data.iter() {
-                    if let Err(err) = db.alert_guild_invalid_info(&ctx, reason).await {
+                    if let Err(err) = guilds::alert_guild_invalid_info(&ctx, reason).await {
                         error!(
                             "Could not alert guild {} of invalid info: {}",
                             (*reason).0.clone(),
@@ -111,14 +154,15 @@ impl EventHandler for Handler {
         match DB
             .read()
             .await
-            .as_ref()
+            .as_deref()
             .unwrap()
             .try_add_guild(guild.id)
             .await
         {
             Ok(inserted) => {
                 if !inserted {
-                    warn!(guild=?guild.id, "Guild was already in db");
+                    // Sent for every guild on startup since the bot receives guild events
+                    debug!(guild=?guild.id, "Guild was already in db");
                 }
             }
             Err(err) => {
@@ -127,6 +171,58 @@ impl EventHandler for Handler {
         }
     }
 
+    async fn channel_delete(&self, ctx: Context, channel: GuildChannel, _: Option<Vec<Message>>) {
+        let db_opt = DB.read().await;
+        if let Err(err) = guilds::channel_deleted(db_opt.as_deref().unwrap(), &ctx, &channel).await
+        {
+            error!(reason = err.to_string(), "Could not handle deleted channel");
+        }
+    }
+
+    async fn guild_role_delete(
+        &self,
+        ctx: Context,
+        guild_id: GuildId,
+        removed_role_id: RoleId,
+        removed_role_data_if_available: Option<Role>,
+    ) {
+        let db_opt = DB.read().await;
+        if let Err(err) = guilds::role_deleted(
+            db_opt.as_deref().unwrap(),
+            &ctx,
+            guild_id,
+            removed_role_id,
+            removed_role_data_if_available.as_ref(),
+        )
+        .await
+        {
+            error!(reason = err.to_string(), "Could not handle deleted role");
+        }
+    }
+
+    async fn guild_delete(&self, _: Context, incomplete: UnavailableGuild, _: Option<Guild>) {
+        // Outages are reported the same way as removals
+        if incomplete.unavailable {
+            warn!(guild=?incomplete.id, "Guild became unavailable");
+            return;
+        }
+        info!(
+            guild=?incomplete.id,
+            retention_days = guilds::RETENTION_DAYS,
+            "Removed from guild. Keeping its settings until they are purged"
+        );
+        if let Err(err) = DB
+            .read()
+            .await
+            .as_deref()
+            .unwrap()
+            .remove_guild(incomplete.id, Utc::now())
+            .await
+        {
+            error!(reason = err.to_string(), "Could not remove guild");
+        }
+    }
+
     async fn ready(&self, ctx: Context, ready: Ready) {
         info!("{} has connected!", ready.user.name);
 
@@ -148,7 +244,7 @@ impl EventHandler for Handler {
         if let Err(err) = DB
             .read()
             .await
-            .as_ref()
+            .as_deref()
             .unwrap()
             .update_guilds(&ready.guilds)
             .await
@@ -159,16 +255,25 @@ impl EventHandler for Handler {
         let commands = vec![
             commands::enable::register(),
             commands::disable::register(),
+            commands::manager_role::register(),
+            commands::role_panel::register(),
             commands::set_alert_channel::register(),
             commands::set_alert_role::register(),
             commands::subscribe::register(),
+            commands::unsubscribe::register(),
             commands::announcement::register(),
+            commands::codes::register(),
+            commands::status::register(),
+            commands::test_alert::register(),
         ];
 
         commands.global_register_all(&ctx.http).await;
 
+        outbox::spawn(ctx.clone());
+
+        let admin = self.admin.clone();
         tokio::spawn(async move {
-            Self::run_alerts(ctx.clone()).await;
+            Self::run_alerts(ctx.clone(), admin).await;
         });
     }
 
@@ -176,20 +281,54 @@ impl EventHandler for Handler {
         if let Interaction::Command(command) = interaction {
             info!("Received interaction from {}", command.user.name);
 
-            let content = match command.data.name.as_str() {
-                commands::enable::CMD_NAME => Some(commands::enable::run(&command).await),
-                commands::disable::CMD_NAME => Some(commands::disable::run(&command).await),
+            let db_opt = DB.read().await;
+            let db = db_opt.as_deref().unwrap();
+            let allowed = !commands::CONFIGURATION.contains(&command.data.name.as_str())
+                || match commands::can_configure(&command, db).await {
+                    Ok(allowed) => allowed,
+                    Err(err) => {
+                        error!(reason = err.to_string(), "Could not check permissions");
+                        false
+                    }
+                };
+            let response = match command.data.name.as_str() {
+                name if !allowed => {
+                    warn!(user=?command.user.id, command = name, "Member may not configure the bot");
+                    Some(CreateInteractionResponseMessage::new().ephemeral(true).content(
+                        "You need the Manage Server permission or the manager role to use this command.",
+                    ))
+                }
+                commands::enable::CMD_NAME => {
+                    Some(reply(commands::enable::run(&command, db).await))
+                }
+                commands::disable::CMD_NAME => {
+                    Some(reply(commands::disable::run(&command, db).await))
+                }
+                commands::manager_role::CMD_NAME => {
+                    Some(reply(commands::manager_role::run(&command, db).await))
+                }
                 commands::set_alert_channel::CMD_NAME => {
-                    Some(commands::set_alert_channel::run(&command).await)
+                    Some(reply(commands::set_alert_channel::run(&command, db).await))
                 }
                 commands::set_alert_role::CMD_NAME => {
-                    Some(commands::set_alert_role::run(&command).await)
+                    Some(reply(commands::set_alert_role::run(&command, db).await))
                 }
                 commands::subscribe::CMD_NAME => {
-                    Some(commands::subscribe::run(&command, &ctx).await)
+                    Some(reply(commands::subscribe::run(&command, &ctx, db).await))
+                }
+                commands::unsubscribe::CMD_NAME => {
+                    Some(reply(commands::unsubscribe::run(&command, &ctx, db).await))
                 }
-                commands::announcement::CMD_NAME => {
-                    Some(commands::announcement::run(&command, &ctx, &self.admin).await)
+                commands::announcement::CMD_NAME => Some(reply(
+                    commands::announcement::run(&command, &ctx, db, &self.admin).await,
+                )),
+                commands::role_panel::CMD_NAME => {
+                    Some(commands::role_panel::run(&command, &ctx, db).await)
+                }
+                commands::codes::CMD_NAME => Some(commands::codes::run(&command, db).await),
+                commands::status::CMD_NAME => Some(commands::status::run(&command, &ctx, db).await),
+                commands::test_alert::CMD_NAME => {
+                    Some(commands::test_alert::run(&command, &ctx, db).await)
                 }
                 _ => {
                     warn!("Received invalid command");
@@ -197,13 +336,26 @@ impl EventHandler for Handler {
                 }
             };
 
-            if let Some(content) = content {
-                let data = CreateInteractionResponseMessage::new().content(content);
+            if let Some(data) = response {
                 let builder = CreateInteractionResponse::Message(data);
                 if let Err(why) = command.create_response(&ctx.http, builder).await {
                     error!("Cannot respond to slash command: {why}")
                 }
             }
+        } else if let Interaction::Component(component) = interaction {
+            let db_opt = DB.read().await;
+            let db = db_opt.as_deref().unwrap();
+            let Some(data) = commands::role_panel::handle(&component, &ctx, db).await else {
+                warn!(
+                    custom_id = component.data.custom_id,
+                    "Received invalid component"
+                );
+                return;
+            };
+            let builder = CreateInteractionResponse::Message(data);
+            if let Err(why) = component.create_response(&ctx.http, builder).await {
+                error!("Cannot respond to component: {why}")
+            }
         }
     }
 }
@@ -0,0 +1,71 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use libsql::Connection;
+use serenity::{all::GatewayIntents, Client};
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::{mpsc, Mutex, RwLock};
+
+use crate::db::{Store, TursoDb};
+use crate::scraper::ScraperEvent;
+
+mod alert;
+mod commands;
+#[cfg(feature = "standalone")]
+pub mod config;
+mod db;
+mod games;
+mod guilds;
+mod handler;
+mod outbox;
+mod scraper;
+
+lazy_static! {
+    static ref CODE_CHAN: Mutex<Option<Receiver<ScraperEvent>>> = Mutex::new(None);
+    static ref DB: RwLock<Option<Box<dyn Store>>> = RwLock::new(None);
+}
+
+#[macro_use]
+extern crate tracing;
+
+static SCRAPER_INTERVAL: u64 = 3600;
+
+/// Migrates the schema on `client` and makes it the database used by the bot.
+pub async fn init_db(client: Connection) -> Result<()> {
+    info!("Initializing db");
+    if let Err(err) = db::migrations::run(&client).await {
+        return Err(anyhow!(
+            "Cannot initialize db. Failed to migrate schema: {}",
+            err.to_string()
+        ));
+    }
+
+    let db = TursoDb::new(Arc::new(client)).await?;
+    if let Err(err) = db.seed_games().await {
+        return Err(anyhow!(
+            "Cannot initialize db. Failed to seed games: {}",
+            err.to_string()
+        ));
+    }
+    *DB.write().await = Some(Box::new(db));
+    Ok(())
+}
+
+/// Starts the scrapers and builds the Discord client consuming their codes.
+///
+/// [`init_db`] has to be called before the client is started.
+pub async fn build_client(token: &str, admin: String) -> Result<Client> {
+    let (tx, rx) = mpsc::channel::<ScraperEvent>(32);
+
+    let mut glob_chan = CODE_CHAN.lock().await;
+    *glob_chan = Some(rx);
+    drop(glob_chan);
+
+    scraper::spawn_all(scraper::sources(SCRAPER_INTERVAL), tx);
+
+    let client = Client::builder(token, GatewayIntents::GUILDS)
+        .event_handler(handler::Handler { admin })
+        .await?;
+    Ok(client)
+}
@@ -0,0 +1,272 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use futures::{stream, StreamExt};
+use lazy_static::lazy_static;
+use serenity::all::{Context, MessageId};
+use serenity::http::HttpError;
+use tokio::sync::Notify;
+
+use crate::alert::Alert;
+use crate::db::{GuildUpdate, OutboxEntry, Recipient, SentAlert, Store};
+use crate::scraper::Backoff;
+use crate::DB;
+
+/// How often the outbox is checked for retries when nothing wakes the worker.
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+/// Attempts after which an alert is dead-lettered.
+const MAX_ATTEMPTS: i64 = 5;
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(30);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(30 * 60);
+/// Alerts sent at the same time.
+const FANOUT_CONCURRENCY: usize = 16;
+/// Discord error code for "Cannot send messages to this user".
+const CANNOT_MESSAGE_USER: isize = 50007;
+
+lazy_static! {
+    static ref WAKE: Notify = Notify::new();
+}
+
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Starts the worker sending queued alerts. Only the first call starts one.
+pub fn spawn(ctx: Context) {
+    if STARTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    tokio::spawn(async move {
+        run(ctx).await;
+    });
+}
+
+/// Makes the worker check the outbox right away.
+pub fn wake() {
+    WAKE.notify_one();
+}
+
+async fn run(ctx: Context) {
+    info!("Starting outbox worker");
+    loop {
+        if let Err(err) = work(&ctx, DB.read().await.as_deref().unwrap()).await {
+            error!(reason = err.to_string(), "Could not work the outbox");
+        }
+        let _ = tokio::time::timeout(POLL_INTERVAL, WAKE.notified()).await;
+    }
+}
+
+/// Sends every due alert, at most [`FANOUT_CONCURRENCY`] at a time. Requests
+/// go through serenity's http client, which waits out the rate limits of each
+/// route, so busy channels only slow down their own alerts.
+async fn work(ctx: &Context, db: &dyn Store) -> Result<()> {
+    let entries = db.due_outbox(Utc::now()).await?;
+    if !entries.is_empty() {
+        let count = entries.len();
+        let started = Instant::now();
+        stream::iter(entries)
+            .for_each_concurrent(FANOUT_CONCURRENCY, |entry| async move {
+                if let Err(err) = process(ctx, db, &entry).await {
+                    error!(reason = err.to_string(), recipient=?entry.update.recipient, "Could not process queued alert");
+                }
+            })
+            .await;
+        info!(
+            alerts = count,
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "Fan-out finished"
+        );
+    }
+
+    refresh_expired(ctx, db).await
+}
+
+/// Updates sent alerts whose codes expired, or were listed again, so members
+/// do not try dead codes. Failed updates are retried on the next poll.
+async fn refresh_expired(ctx: &Context, db: &dyn Store) -> Result<()> {
+    let alerts = db.stale_alerts().await?;
+    let refreshes: Vec<_> = alerts.iter().map(|sent| refresh(ctx, db, sent)).collect();
+    stream::iter(refreshes)
+        .buffer_unordered(FANOUT_CONCURRENCY)
+        .for_each(|result| async move {
+            if let Err(err) = result {
+                error!(reason = err.to_string(), "Could not update expired alert");
+            }
+        })
+        .await;
+    Ok(())
+}
+
+async fn refresh(ctx: &Context, db: &dyn Store, sent: &SentAlert) -> Result<()> {
+    match edit_alert(ctx, sent).await {
+        Ok(()) => {
+            info!(recipient=?sent.recipient, message=?sent.message, "Updated expired codes of alert");
+        }
+        Err(err) if classify(&err) == Failure::Permanent => {
+            // Deleted or no longer accessible, it will not get any better
+            warn!(recipient=?sent.recipient, message=?sent.message, reason = err.to_string(), "Could not update alert. Skipping it");
+        }
+        Err(err) => return Err(err),
+    }
+    db.mark_alert_updated(sent.message).await
+}
+
+async fn edit_alert(ctx: &Context, sent: &SentAlert) -> Result<()> {
+    let message = sent.channel.message(&ctx.http, sent.message).await?;
+    let alert = Alert::new(
+        sent.game,
+        message.mention_roles.first().copied(),
+        &sent.codes,
+    );
+    let edit = if message.embeds.is_empty() {
+        alert.text_edit()
+    } else {
+        alert.embed_edit()
+    };
+    sent.channel
+        .edit_message(&ctx.http, sent.message, edit)
+        .await?;
+    Ok(())
+}
+
+async fn process(ctx: &Context, db: &dyn Store, entry: &OutboxEntry) -> Result<()> {
+    let update = &entry.update;
+    let err = match send(update, ctx).await {
+        Ok(message) => {
+            db.complete_outbox(entry.id, message).await?;
+            return Ok(());
+        }
+        Err(err) => err,
+    };
+
+    let reason = err.to_string();
+    if let Recipient::User(user) = update.recipient {
+        if is_closed_dm(&err) {
+            // The user left every shared server or blocked DMs, stop trying
+            warn!(user=?user, reason, "Cannot send direct messages to user. Unsubscribing them");
+            db.dead_letter_outbox(entry.id, &reason).await?;
+            // Deletes the entry along with the rest of the user's alerts
+            db.unsubscribe_user(user, None).await?;
+            return Ok(());
+        }
+    }
+
+    let failure = classify(&err);
+    if should_retry(failure, entry.attempts) {
+        let delay =
+            Backoff::resume(RETRY_BASE_DELAY, RETRY_MAX_DELAY, entry.attempts as u32).next_delay();
+        warn!(
+            recipient=?update.recipient,
+            game=?update.game,
+            attempt = entry.attempts + 1,
+            delay_ms = delay.as_millis() as u64,
+            reason,
+            "Could not send codes. Retrying"
+        );
+        db.retry_outbox(
+            entry.id,
+            Utc::now() + chrono::Duration::from_std(delay)?,
+            &reason,
+        )
+        .await?;
+    } else {
+        error!(
+            recipient=?update.recipient,
+            game=?update.game,
+            attempts = entry.attempts + 1,
+            failure=?failure,
+            reason,
+            "Could not send codes. Giving up"
+        );
+        db.dead_letter_outbox(entry.id, &reason).await?;
+    }
+    Ok(())
+}
+
+/// Posts the alert. Returns `None` if there was nothing to send.
+async fn send(update: &GuildUpdate, ctx: &Context) -> Result<Option<MessageId>> {
+    if !update.has_codes() {
+        info!(recipient=?update.recipient, game=?update.game, "No new codes to send");
+        return Ok(None);
+    }
+    let Some(alert_chan) = update.chan else {
+        return Err(anyhow!("No alert channel set"));
+    };
+    let alert = Alert::new(update.game, update.role, update.codes.as_deref().unwrap());
+    let message = alert.post(&ctx.http, alert_chan).await?;
+    info!(recipient=?update.recipient, game=?update.game, "Sent codes");
+    Ok(Some(message))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    /// Might succeed later, e.g. Discord being unavailable or rate limiting
+    Transient,
+    /// Will fail again, e.g. the channel is gone or the bot lost access to it
+    Permanent,
+}
+
+fn classify(err: &anyhow::Error) -> Failure {
+    match err.downcast_ref::<serenity::Error>() {
+        Some(serenity::Error::Http(HttpError::UnsuccessfulRequest(response))) => {
+            classify_status(response.status_code.as_u16())
+        }
+        Some(serenity::Error::Http(HttpError::Request(_))) => Failure::Transient,
+        Some(serenity::Error::Io(_)) => Failure::Transient,
+        _ => Failure::Permanent,
+    }
+}
+
+/// Discord refuses to open a DM with users sharing no server with the bot or
+/// having DMs disabled.
+fn is_closed_dm(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<serenity::Error>(),
+        Some(serenity::Error::Http(HttpError::UnsuccessfulRequest(response)))
+            if response.error.code == CANNOT_MESSAGE_USER
+    )
+}
+
+fn classify_status(status: u16) -> Failure {
+    if status == 429 || status >= 500 {
+        Failure::Transient
+    } else {
+        Failure::Permanent
+    }
+}
+
+fn should_retry(failure: Failure, attempts: i64) -> bool {
+    failure == Failure::Transient && attempts + 1 < MAX_ATTEMPTS
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::{classify, classify_status, should_retry, Failure, MAX_ATTEMPTS};
+
+    #[test]
+    fn discord_errors() {
+        // Unavailable and rate limited
+        assert_eq!(classify_status(502), Failure::Transient);
+        assert_eq!(classify_status(429), Failure::Transient);
+        // Missing access and unknown channel
+        assert_eq!(classify_status(403), Failure::Permanent);
+        assert_eq!(classify_status(404), Failure::Permanent);
+    }
+
+    #[test]
+    fn missing_channel_is_permanent() {
+        assert_eq!(
+            classify(&anyhow!("No alert channel set")),
+            Failure::Permanent
+        );
+    }
+
+    #[test]
+    fn retries_are_limited() {
+        assert!(should_retry(Failure::Transient, 0));
+        assert!(!should_retry(Failure::Transient, MAX_ATTEMPTS - 1));
+        assert!(!should_retry(Failure::Permanent, 0));
+    }
+}
@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::Deserialize;
+use serenity::async_trait;
+
+use super::{parse_rewards, retrieve_page, CodeSource, ScrapeError, ScrapedCode};
+use crate::games::Game;
+
+/// Reads codes from the hoyo-codes JSON API.
+pub struct HoyoCodesSource {
+    name: &'static str,
+    url: &'static str,
+    game: Game,
+    interval: Duration,
+}
+
+impl HoyoCodesSource {
+    pub fn genshin(interval: Duration) -> Self {
+        Self {
+            name: "hoyo-codes-genshin",
+            url: "https://hoyo-codes.seria.moe/codes?game=genshin",
+            game: Game::Genshin,
+            interval,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CodesResponse {
+    codes: Vec<CodeEntry>,
+}
+
+#[derive(Deserialize)]
+struct CodeEntry {
+    code: String,
+    #[serde(default)]
+    rewards: Option<String>,
+}
+
+#[async_trait]
+impl CodeSource for HoyoCodesSource {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn game(&self) -> Game {
+        self.game
+    }
+
+    fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    async fn fetch(&self) -> Result<String> {
+        retrieve_page(self.url).await
+    }
+
+    fn parse(&self, page: &str) -> Result<Vec<ScrapedCode>, ScrapeError> {
+        parse_codes(page)
+    }
+}
+
+fn parse_codes(page: &str) -> Result<Vec<ScrapedCode>, ScrapeError> {
+    let response: CodesResponse =
+        serde_json::from_str(page).map_err(|err| ScrapeError::LayoutChanged(err.to_string()))?;
+
+    let codes: Vec<ScrapedCode> = response
+        .codes
+        .into_iter()
+        .filter(|entry| !entry.code.trim().is_empty())
+        .map(|entry| ScrapedCode {
+            code: entry.code.trim().to_string(),
+            rewards: parse_rewards(&entry.rewards.unwrap_or_default()),
+            expires_at: None,
+            is_new: false,
+            source: None,
+        })
+        .collect();
+
+    if codes.is_empty() {
+        return Err(ScrapeError::NoCodes);
+    }
+
+    Ok(codes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_codes;
+    use crate::scraper::ScrapeError;
+
+    #[test]
+    fn codes_with_rewards() {
+        let codes =
+            parse_codes(include_str!("../../tests/fixtures/hoyo_codes/genshin.json")).unwrap();
+        assert_eq!(codes.len(), 2);
+        assert_eq!(codes[0].code, "GENSHINGIFT");
+        assert_eq!(
+            codes[0].rewards,
+            vec!["50 Primogems", "3 Hero's Wit", "10,000 Mora"]
+        );
+        assert_eq!(codes[1].code, "LUNARNEWYEAR");
+        assert!(codes[1].rewards.is_empty());
+    }
+
+    #[test]
+    fn no_codes_listed() {
+        assert_eq!(
+            parse_codes(r#"{"codes": [], "game": "genshin"}"#).unwrap_err(),
+            ScrapeError::NoCodes
+        );
+    }
+
+    #[test]
+    fn not_json() {
+        assert!(matches!(
+            parse_codes("<html><body>Bad Gateway</body></html>"),
+            Err(ScrapeError::LayoutChanged(_))
+        ));
+    }
+}
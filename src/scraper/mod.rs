@@ -0,0 +1,266 @@
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use serenity::async_trait;
+use tokio::sync::mpsc::Sender;
+
+mod hoyo_codes;
+mod prydwen;
+mod resilience;
+
+use crate::games::Game;
+pub use hoyo_codes::HoyoCodesSource;
+pub use prydwen::PrydwenSource;
+pub use resilience::Backoff;
+use resilience::CircuitBreaker;
+
+/// Retries of a failed scrape before the whole cycle counts as failed.
+const RETRY_ATTEMPTS: u32 = 4;
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(5);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(300);
+/// Failed cycles in a row after which a source is considered degraded.
+const BREAKER_THRESHOLD: u32 = 3;
+/// Intervals a degraded source is left alone before it is tried again.
+const BREAKER_COOLDOWN_INTERVALS: u32 = 3;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScrapedCode {
+    pub code: String,
+    pub rewards: Vec<String>,
+    pub expires_at: Option<NaiveDate>,
+    /// Whether the source marks the code as newly released
+    pub is_new: bool,
+    /// Name of the source that reported the code. Set by the scraper task.
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+/// The codes a single source reported in one scrape.
+#[derive(Debug, Clone)]
+pub struct SourceCodes {
+    pub source: &'static str,
+    pub game: Game,
+    pub codes: Vec<ScrapedCode>,
+}
+
+/// Everything a scraper task reports to the handler.
+#[derive(Debug, Clone)]
+pub enum ScraperEvent {
+    Codes(SourceCodes),
+    /// The source is available but lists no codes, every code it listed
+    /// before expired
+    NoCodes {
+        source: &'static str,
+        game: Game,
+    },
+    Degraded {
+        source: &'static str,
+        reason: String,
+    },
+    Recovered {
+        source: &'static str,
+    },
+}
+
+/// Why a page could not be turned into codes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScrapeError {
+    /// The page does not look like the layout the source was written for
+    LayoutChanged(String),
+    /// The page has the expected layout but currently lists no codes
+    NoCodes,
+}
+
+impl Display for ScrapeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ScrapeError::LayoutChanged(reason) => write!(f, "Page layout changed: {reason}"),
+            ScrapeError::NoCodes => write!(f, "Page lists no codes"),
+        }
+    }
+}
+
+impl std::error::Error for ScrapeError {}
+
+/// A site listing redeemable codes.
+///
+/// Every source runs in its own task on its own interval and sends its results
+/// to the channel consumed by the handler.
+#[async_trait]
+pub trait CodeSource: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// The game all codes of this source belong to.
+    fn game(&self) -> Game;
+
+    fn interval(&self) -> Duration;
+
+    async fn fetch(&self) -> Result<String>;
+
+    fn parse(&self, page: &str) -> Result<Vec<ScrapedCode>, ScrapeError>;
+}
+
+pub(crate) async fn retrieve_page(url: &str) -> Result<String> {
+    let response = reqwest::get(url).await?;
+    let page_data = response.text().await?;
+    Ok(page_data)
+}
+
+/// Splits a rewards listing like "50 Stellar Jade + 10,000 Credits" into
+/// its rewards. Commas between digits are thousands separators.
+pub(crate) fn parse_rewards(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut rewards = Vec::new();
+    let mut reward = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        let thousands = c == ','
+            && i > 0
+            && chars[i - 1].is_ascii_digit()
+            && chars.get(i + 1).is_some_and(char::is_ascii_digit);
+        if matches!(c, '+' | '\n') || (c == ',' && !thousands) {
+            rewards.push(std::mem::take(&mut reward));
+        } else {
+            reward.push(c);
+        }
+    }
+    rewards.push(reward);
+    rewards
+        .iter()
+        .map(|reward| reward.trim())
+        .filter(|reward| !reward.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+pub fn sources(interval: u64) -> Vec<Arc<dyn CodeSource>> {
+    let interval = Duration::from_secs(interval);
+    vec![
+        Arc::new(PrydwenSource::star_rail(interval)),
+        Arc::new(PrydwenSource::zenless(interval)),
+        Arc::new(HoyoCodesSource::genshin(interval)),
+    ]
+}
+
+pub fn spawn_all(sources: Vec<Arc<dyn CodeSource>>, tx: Sender<ScraperEvent>) {
+    for source in sources {
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            info!(
+                source = source.name(),
+                interval = source.interval().as_secs(),
+                "Starting scraper"
+            );
+            run(source, tx).await
+        });
+    }
+}
+
+async fn scrape(source: &dyn CodeSource) -> Result<Vec<ScrapedCode>> {
+    let page = source.fetch().await?;
+    Ok(source.parse(&page)?)
+}
+
+fn is_no_codes(err: &anyhow::Error) -> bool {
+    matches!(err.downcast_ref(), Some(ScrapeError::NoCodes))
+}
+
+async fn scrape_with_retries(source: &dyn CodeSource) -> Result<Vec<ScrapedCode>> {
+    let mut backoff = Backoff::new(RETRY_BASE_DELAY, RETRY_MAX_DELAY);
+    loop {
+        match scrape(source).await {
+            Ok(codes) => return Ok(codes),
+            Err(err) if !is_no_codes(&err) && backoff.attempt() < RETRY_ATTEMPTS => {
+                let delay = backoff.next_delay();
+                warn!(
+                    source = source.name(),
+                    attempt = backoff.attempt(),
+                    delay_ms = delay.as_millis() as u64,
+                    reason = err.to_string(),
+                    "Scrape failed. Retrying"
+                );
+                tokio::time::sleep(delay).await;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+pub async fn run(source: Arc<dyn CodeSource>, tx: Sender<ScraperEvent>) {
+    let mut breaker = CircuitBreaker::new(
+        BREAKER_THRESHOLD,
+        source.interval() * BREAKER_COOLDOWN_INTERVALS,
+    );
+    loop {
+        let mut events = Vec::new();
+        if breaker.allow() {
+            match scrape_with_retries(source.as_ref()).await {
+                Ok(mut data) => {
+                    for code in data.iter_mut() {
+                        code.source = Some(source.name().to_string());
+                    }
+                    if breaker.record_success() {
+                        info!(source = source.name(), "Source recovered");
+                        events.push(ScraperEvent::Recovered {
+                            source: source.name(),
+                        });
+                    }
+                    info!(
+                        source = source.name(),
+                        amount = &data.len(),
+                        "Retrieved valid codes. Sending to shards"
+                    );
+                    info!(codes=?&data, "Valid codes");
+                    events.push(ScraperEvent::Codes(SourceCodes {
+                        source: source.name(),
+                        game: source.game(),
+                        codes: data,
+                    }));
+                }
+                Err(err) if is_no_codes(&err) => {
+                    // The page is fine, there just is nothing to hand out
+                    if breaker.record_success() {
+                        info!(source = source.name(), "Source recovered");
+                        events.push(ScraperEvent::Recovered {
+                            source: source.name(),
+                        });
+                    }
+                    warn!(source = source.name(), "Source lists no codes");
+                    events.push(ScraperEvent::NoCodes {
+                        source: source.name(),
+                        game: source.game(),
+                    });
+                }
+                Err(err) => {
+                    error!(
+                        source = source.name(),
+                        reason = err.to_string(),
+                        "Could not retrieve valid codes"
+                    );
+                    if breaker.record_failure() {
+                        error!(source = source.name(), "Source degraded");
+                        events.push(ScraperEvent::Degraded {
+                            source: source.name(),
+                            reason: err.to_string(),
+                        });
+                    }
+                }
+            }
+        } else {
+            warn!(source = source.name(), "Source degraded. Skipping scrape");
+        }
+        for event in events {
+            if tx.send(event).await.is_err() {
+                error!(
+                    source = source.name(),
+                    "Code channel closed. Stopping scraper"
+                );
+                return;
+            }
+        }
+        tokio::time::sleep(source.interval()).await;
+    }
+}
@@ -0,0 +1,209 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use lazy_static::lazy_static;
+use regex::Regex;
+use scraper::{Html, Selector};
+use serenity::async_trait;
+
+use super::{parse_rewards, retrieve_page, CodeSource, ScrapeError, ScrapedCode};
+use crate::games::Game;
+
+/// Scrapes the codes box on the Prydwen page of a game.
+pub struct PrydwenSource {
+    name: &'static str,
+    url: &'static str,
+    game: Game,
+    interval: Duration,
+}
+
+impl PrydwenSource {
+    pub fn star_rail(interval: Duration) -> Self {
+        Self {
+            name: "prydwen-hsr",
+            url: "https://www.prydwen.gg/star-rail/",
+            game: Game::StarRail,
+            interval,
+        }
+    }
+
+    pub fn zenless(interval: Duration) -> Self {
+        Self {
+            name: "prydwen-zzz",
+            url: "https://www.prydwen.gg/zenless/",
+            game: Game::ZenlessZoneZero,
+            interval,
+        }
+    }
+}
+
+#[async_trait]
+impl CodeSource for PrydwenSource {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn game(&self) -> Game {
+        self.game
+    }
+
+    fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    async fn fetch(&self) -> Result<String> {
+        retrieve_page(self.url).await
+    }
+
+    fn parse(&self, page: &str) -> Result<Vec<ScrapedCode>, ScrapeError> {
+        scrape_codes(page)
+    }
+}
+
+lazy_static! {
+    static ref EXPIRY: Regex = Regex::new(
+        r"(?i)expires?\s*(?:on)?\s*:?\s*(\d{4}-\d{2}-\d{2}|\d{1,2}/\d{1,2}/\d{4}|[a-z]+\.? \d{1,2}(?:st|nd|rd|th)?,? \d{4})"
+    )
+    .unwrap();
+    static ref ORDINAL: Regex = Regex::new(r"(\d)(?:st|nd|rd|th)").unwrap();
+}
+
+fn parse_expiry(text: &str) -> Option<NaiveDate> {
+    let captured = EXPIRY.captures(text)?.get(1)?.as_str();
+    let normalized = ORDINAL.replace_all(captured, "$1").replace([',', '.'], "");
+    ["%Y-%m-%d", "%d/%m/%Y", "%B %d %Y", "%b %d %Y"]
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(&normalized, format).ok())
+}
+
+fn scrape_codes(page: &str) -> Result<Vec<ScrapedCode>, ScrapeError> {
+    let html = Html::parse_document(page);
+    let code_container_selector = Selector::parse("div.codes").unwrap();
+    let code_selector = Selector::parse(".code").unwrap();
+    let rewards_selector = Selector::parse(".rewards").unwrap();
+    let new_selector = Selector::parse(".new").unwrap();
+
+    let code_container = html
+        .select(&code_container_selector)
+        .next()
+        .ok_or_else(|| ScrapeError::LayoutChanged("No codes div found".to_string()))?;
+
+    let code_count = code_container.child_elements().count();
+    let mut codes = Vec::with_capacity(code_count);
+
+    for dv in code_container.child_elements() {
+        // Older layouts had the code as the first text of the box without a
+        // dedicated element
+        let code_el = dv.select(&code_selector).next().unwrap_or(dv);
+        let code = code_el.text().map(str::trim).find(|text| !text.is_empty());
+        if let Some(code) = code {
+            let rewards = dv
+                .select(&rewards_selector)
+                .flat_map(|el| parse_rewards(&el.text().collect::<String>()))
+                .collect();
+            let expires_at = parse_expiry(&dv.text().collect::<Vec<_>>().join(" "));
+            codes.push(ScrapedCode {
+                code: code.to_string(),
+                rewards,
+                expires_at,
+                is_new: dv.select(&new_selector).next().is_some(),
+                source: None,
+            });
+        }
+    }
+
+    if codes.is_empty() {
+        return Err(ScrapeError::NoCodes);
+    }
+
+    Ok(codes)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::scrape_codes;
+    use crate::scraper::ScrapeError;
+
+    fn codes(page: &str) -> Vec<String> {
+        scrape_codes(page)
+            .unwrap()
+            .into_iter()
+            .map(|code| code.code)
+            .collect()
+    }
+
+    #[test]
+    fn current_layout() {
+        let codes =
+            scrape_codes(include_str!("../../tests/fixtures/prydwen/current.html")).unwrap();
+        assert_eq!(codes.len(), 3);
+
+        assert_eq!(codes[0].code, "STARRAILGIFT");
+        assert_eq!(
+            codes[0].rewards,
+            vec!["50 Stellar Jade", "2 Traveler's Guide", "5000 Credits"]
+        );
+        assert_eq!(codes[0].expires_at, None);
+        assert!(!codes[0].is_new);
+
+        assert_eq!(codes[1].code, "SPRINGFEST24");
+        assert_eq!(codes[1].rewards, vec!["100 Stellar Jade", "10,000 Credits"]);
+        assert_eq!(codes[1].expires_at, NaiveDate::from_ymd_opt(2024, 3, 1));
+        assert!(codes[1].is_new);
+
+        assert_eq!(codes[2].code, "HSRVER20");
+        assert_eq!(
+            codes[2].rewards,
+            vec!["60 Stellar Jade", "5 Refined Aether"]
+        );
+        assert_eq!(codes[2].expires_at, NaiveDate::from_ymd_opt(2024, 2, 29));
+    }
+
+    #[test]
+    fn legacy_layout() {
+        assert_eq!(
+            codes(include_str!("../../tests/fixtures/prydwen/legacy.html")),
+            vec!["STARRAILGIFT", "HSRGRANDOPEN1", "HSRGRANDOPEN2"]
+        );
+    }
+
+    #[test]
+    fn malformed_page() {
+        let codes =
+            scrape_codes(include_str!("../../tests/fixtures/prydwen/malformed.html")).unwrap();
+        // The unclosed box swallows the truncated one
+        assert_eq!(codes.len(), 2);
+        assert_eq!(codes[0].code, "STARRAILGIFT");
+        assert_eq!(codes[0].rewards, vec!["50 Stellar Jade", "5000 Credits"]);
+        assert_eq!(codes[1].code, "BROKENTAGS");
+        assert_eq!(codes[1].rewards, vec!["30 Stellar Jade"]);
+        assert_eq!(codes[1].expires_at, None);
+    }
+
+    #[test]
+    fn no_codes_listed() {
+        assert_eq!(
+            scrape_codes(include_str!("../../tests/fixtures/prydwen/no_codes.html")).unwrap_err(),
+            ScrapeError::NoCodes
+        );
+    }
+
+    #[test]
+    fn empty_page() {
+        assert!(matches!(
+            scrape_codes(include_str!("../../tests/fixtures/prydwen/empty.html")),
+            Err(ScrapeError::LayoutChanged(_))
+        ));
+    }
+
+    #[test]
+    fn redesigned_layout() {
+        assert!(matches!(
+            scrape_codes(include_str!("../../tests/fixtures/prydwen/redesigned.html")),
+            Err(ScrapeError::LayoutChanged(_))
+        ));
+    }
+}
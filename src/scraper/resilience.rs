@@ -0,0 +1,168 @@
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+/// Exponential backoff with full jitter between retries of a single scrape.
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max,
+            attempt: 0,
+        }
+    }
+
+    /// Continues a backoff after `attempt` earlier attempts.
+    pub fn resume(base: Duration, max: Duration, attempt: u32) -> Self {
+        Self { base, max, attempt }
+    }
+
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// Delay to wait before the next attempt. Picked uniformly between zero and
+    /// `base * 2^attempt`, capped at `max`.
+    pub fn next_delay(&mut self) -> Duration {
+        let ceiling = self
+            .base
+            .saturating_mul(2u32.saturating_pow(self.attempt))
+            .min(self.max);
+        self.attempt += 1;
+        let millis = ceiling.as_millis() as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(0..=millis))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BreakerState {
+    Closed,
+    Open(Instant),
+    HalfOpen,
+}
+
+/// Tracks consecutive failed scrapes of a source.
+///
+/// After `threshold` failures in a row the breaker opens and the source is
+/// considered degraded. While open, scrapes are skipped until `cooldown` has
+/// passed, after which a single trial scrape decides whether it closes again.
+pub struct CircuitBreaker {
+    state: BreakerState,
+    failures: u32,
+    threshold: u32,
+    cooldown: Duration,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            state: BreakerState::Closed,
+            failures: 0,
+            threshold,
+            cooldown,
+        }
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.state != BreakerState::Closed
+    }
+
+    /// Whether a scrape should be attempted right now.
+    pub fn allow(&mut self) -> bool {
+        match self.state {
+            BreakerState::Closed | BreakerState::HalfOpen => true,
+            BreakerState::Open(since) => {
+                if since.elapsed() >= self.cooldown {
+                    self.state = BreakerState::HalfOpen;
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    /// Returns true if the source was degraded before this success.
+    pub fn record_success(&mut self) -> bool {
+        let recovered = self.is_degraded();
+        self.failures = 0;
+        self.state = BreakerState::Closed;
+        recovered
+    }
+
+    /// Returns true if this failure made the source degraded.
+    pub fn record_failure(&mut self) -> bool {
+        self.failures += 1;
+        match self.state {
+            BreakerState::Closed if self.failures >= self.threshold => {
+                self.state = BreakerState::Open(Instant::now());
+                true
+            }
+            BreakerState::HalfOpen => {
+                self.state = BreakerState::Open(Instant::now());
+                false
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Backoff, CircuitBreaker};
+
+    #[test]
+    fn delays_stay_below_the_ceiling() {
+        let base = Duration::from_millis(100);
+        let max = Duration::from_secs(1);
+        let mut backoff = Backoff::new(base, max);
+        for attempt in 0..40 {
+            let ceiling = (base * 2u32.saturating_pow(attempt)).min(max);
+            assert!(backoff.next_delay() <= ceiling);
+        }
+        assert_eq!(backoff.attempt(), 40);
+    }
+
+    #[test]
+    fn opens_at_the_threshold() {
+        let mut breaker = CircuitBreaker::new(3, Duration::from_secs(60));
+        assert!(!breaker.record_failure());
+        assert!(!breaker.record_failure());
+        assert!(breaker.allow());
+        assert!(breaker.record_failure());
+        assert!(breaker.is_degraded());
+        assert!(!breaker.allow());
+
+        // Further failures do not report it again
+        assert!(!breaker.record_failure());
+    }
+
+    #[test]
+    fn failed_trial_reopens_without_degrading_again() {
+        let mut breaker = CircuitBreaker::new(1, Duration::ZERO);
+        assert!(breaker.record_failure());
+        // The cooldown passed, a trial scrape is allowed
+        assert!(breaker.allow());
+        assert!(!breaker.record_failure());
+        assert!(breaker.is_degraded());
+    }
+
+    #[test]
+    fn success_reports_recovery() {
+        let mut breaker = CircuitBreaker::new(1, Duration::ZERO);
+        assert!(!breaker.record_success());
+        assert!(breaker.record_failure());
+        assert!(breaker.allow());
+        assert!(breaker.record_success());
+        assert!(!breaker.is_degraded());
+        assert!(!breaker.record_success());
+    }
+}